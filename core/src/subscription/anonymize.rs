@@ -0,0 +1,161 @@
+//! Prefix-preserving IP address anonymization.
+//!
+//! Implements Crypto-PAn (Fan, Xu, Ammar & Moore, "Prefix-Preserving IP Address Anonymization"):
+//! addresses are encrypted one bit at a time, most-significant bit first, so that any two
+//! addresses sharing a k-bit prefix anonymize to addresses that also share a k-bit prefix. This
+//! is what `ipcrypt`-style whole-address encryption cannot offer, and it's what makes an
+//! anonymized trace still useful for subnet-level analysis.
+//!
+//! ## Example
+//! ```
+//! use retina_core::subscription::anonymize::CryptoPan;
+//! use std::net::Ipv4Addr;
+//!
+//! let key = [0u8; 16];
+//! let anonymizer = CryptoPan::new(key);
+//! let addr: Ipv4Addr = "10.0.0.1".parse().unwrap();
+//! let anon = anonymizer.anonymize_v4(addr);
+//! assert_eq!(anonymizer.deanonymize_v4(anon), addr);
+//! ```
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+/// A prefix-preserving address anonymizer, keyed by a 128-bit AES key.
+///
+/// Bit `i` of an anonymized address is the original bit XORed with the low bit of
+/// `AES(key, pad(orig_bits[0..i]))`, where `pad` zero-fills the prefix out to a full AES block.
+/// Since the pseudorandom bit at position `i` depends only on original bits strictly before `i`,
+/// the mapping is a bijection: `deanonymize` recovers each original bit in the same MSB-first
+/// order, using the original bits already recovered in earlier steps as the PRF input.
+pub struct CryptoPan {
+    cipher: Aes128,
+}
+
+impl CryptoPan {
+    /// Builds an anonymizer from a 128-bit key.
+    pub fn new(key: [u8; 16]) -> Self {
+        CryptoPan {
+            cipher: Aes128::new(GenericArray::from_slice(&key)),
+        }
+    }
+
+    /// `LSB(AES(key, pad(prefix[0..n])))`: `prefix` is a 128-bit block holding the address
+    /// left-justified in its most significant bits; only bits `0..n` are used, so the caller
+    /// doesn't need to pre-zero anything beyond them.
+    fn prf_bit(&self, prefix: &[u8; 16], n: usize) -> u8 {
+        let mut masked = *prefix;
+        for bit in n..128 {
+            masked[bit / 8] &= !(0x80 >> (bit % 8));
+        }
+        let mut block = GenericArray::clone_from_slice(&masked);
+        self.cipher.encrypt_block(&mut block);
+        block[15] & 1
+    }
+
+    /// Anonymizes the first `n_bits` of `addr` (left-justified in a 16-byte block), returning a
+    /// same-sized block with only those bits transformed.
+    fn anonymize_bits(&self, addr: &[u8], n_bits: usize) -> Vec<u8> {
+        let mut block = [0u8; 16];
+        block[..addr.len()].copy_from_slice(addr);
+        let mut out = block;
+        for i in 0..n_bits {
+            let orig_bit = (block[i / 8] >> (7 - i % 8)) & 1;
+            let anon_bit = orig_bit ^ self.prf_bit(&block, i);
+            if anon_bit == 1 {
+                out[i / 8] |= 0x80 >> (i % 8);
+            } else {
+                out[i / 8] &= !(0x80 >> (i % 8));
+            }
+        }
+        out[..addr.len()].to_vec()
+    }
+
+    /// Reverses `anonymize_bits`, recovering the original bits one at a time, MSB first, using
+    /// the original prefix recovered so far as the PRF input for the next bit.
+    fn deanonymize_bits(&self, anon: &[u8], n_bits: usize) -> Vec<u8> {
+        let mut anon_block = [0u8; 16];
+        anon_block[..anon.len()].copy_from_slice(anon);
+        let mut orig_block = [0u8; 16];
+        for i in 0..n_bits {
+            let anon_bit = (anon_block[i / 8] >> (7 - i % 8)) & 1;
+            let orig_bit = anon_bit ^ self.prf_bit(&orig_block, i);
+            if orig_bit == 1 {
+                orig_block[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        orig_block[..anon.len()].to_vec()
+    }
+
+    /// Anonymizes a 32-bit IPv4 address, preserving shared prefixes.
+    pub fn anonymize_v4(&self, addr: Ipv4Addr) -> Ipv4Addr {
+        let bits = self.anonymize_bits(&addr.octets(), 32);
+        Ipv4Addr::new(bits[0], bits[1], bits[2], bits[3])
+    }
+
+    /// Recovers the original IPv4 address from one produced by `anonymize_v4` with this key.
+    pub fn deanonymize_v4(&self, addr: Ipv4Addr) -> Ipv4Addr {
+        let bits = self.deanonymize_bits(&addr.octets(), 32);
+        Ipv4Addr::new(bits[0], bits[1], bits[2], bits[3])
+    }
+
+    /// Anonymizes a 128-bit IPv6 address, preserving shared prefixes.
+    pub fn anonymize_v6(&self, addr: Ipv6Addr) -> Ipv6Addr {
+        let bits = self.anonymize_bits(&addr.octets(), 128);
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&bits);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Recovers the original IPv6 address from one produced by `anonymize_v6` with this key.
+    pub fn deanonymize_v6(&self, addr: Ipv6Addr) -> Ipv6Addr {
+        let bits = self.deanonymize_bits(&addr.octets(), 128);
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&bits);
+        Ipv6Addr::from(octets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v4_round_trips() {
+        let anonymizer = CryptoPan::new([0u8; 16]);
+        let addr: Ipv4Addr = "192.168.1.1".parse().unwrap();
+        let anon = anonymizer.anonymize_v4(addr);
+        assert_ne!(anon, addr);
+        assert_eq!(anonymizer.deanonymize_v4(anon), addr);
+    }
+
+    #[test]
+    fn v6_round_trips() {
+        let anonymizer = CryptoPan::new([7u8; 16]);
+        let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let anon = anonymizer.anonymize_v6(addr);
+        assert_ne!(anon, addr);
+        assert_eq!(anonymizer.deanonymize_v6(anon), addr);
+    }
+
+    #[test]
+    fn v4_preserves_shared_prefix() {
+        let anonymizer = CryptoPan::new([42u8; 16]);
+        let a: Ipv4Addr = "10.0.0.1".parse().unwrap();
+        let b: Ipv4Addr = "10.0.0.2".parse().unwrap();
+        let anon_a = anonymizer.anonymize_v4(a);
+        let anon_b = anonymizer.anonymize_v4(b);
+        // Both share a 24-bit prefix pre-anonymization, so the top 3 octets must still match.
+        assert_eq!(anon_a.octets()[..3], anon_b.octets()[..3]);
+    }
+
+    #[test]
+    fn v4_different_keys_produce_different_output() {
+        let addr: Ipv4Addr = "172.16.0.1".parse().unwrap();
+        let anon1 = CryptoPan::new([1u8; 16]).anonymize_v4(addr);
+        let anon2 = CryptoPan::new([2u8; 16]).anonymize_v4(addr);
+        assert_ne!(anon1, anon2);
+    }
+}
@@ -24,14 +24,18 @@ use crate::protocols::packet::ipv4::Ipv4;
 use crate::protocols::packet::tcp::Tcp;
 use crate::protocols::packet::Packet;
 use crate::protocols::stream::{ConnParser, Session, SessionData};
+use crate::subscription::quantile::P2Quantile;
 use crate::subscription::{Level, Subscribable, Subscription, Trackable};
 
+use std::any::Any;
 // use std::collections::HashMap;
 // use std::collections::HashSet;
 use std::fmt;
 // use std::ops::Index;
 
 use anyhow::Result;
+#[cfg(feature = "conn-trace")]
+use log::trace;
 // use ndarray::Array;
 // use ndarray_stats::SummaryStatisticsExt;
 use serde::ser::{SerializeStruct, Serializer};
@@ -45,6 +49,12 @@ lazy_static! {
     static ref TSC_HZ: f64 = unsafe { rte_get_tsc_hz() as f64 };
 }
 
+/// Interval between intermediate feature snapshots for a connection that hasn't yet terminated,
+/// in nanoseconds. Short enough that a multi-minute flow (e.g. a long-lived exfiltration) reports
+/// several times before `on_terminate`, long enough that ordinary short connections never trigger
+/// it at all.
+const SNAPSHOT_INTERVAL_NS: u64 = 10_000_000_000;
+
 /// A connection features record.
 ///
 /// This subscribable type returns general information regarding TCP and UDP connections but does
@@ -56,6 +66,15 @@ pub struct ConnFeatures {
     pub sni: String,
     /// Features,
     pub features: Vec<f64>,
+    /// Sequence number of this record within the connection: `1` the first time features are
+    /// reported, incrementing by one on every later snapshot, including the terminal one.
+    pub seq: u32,
+    /// Time elapsed since the connection began, in nanoseconds.
+    pub elapsed_ns: u64,
+    /// Opaque correlation token set via `TrackedConnFeatures::set_user_token`, `0` if never set.
+    /// Lets external bookkeeping (cross-connection aggregation, an online model fed one sample
+    /// per connection) key off a value it assigned itself, instead of `FiveTuple`.
+    pub user_token: u64,
 }
 
 impl ConnFeatures {
@@ -67,9 +86,12 @@ impl Serialize for ConnFeatures {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("ConnFeatures", 2)?;
+        let mut state = serializer.serialize_struct("ConnFeatures", 5)?;
         state.serialize_field("sni", &self.sni)?;
         state.serialize_field("fts", &self.features)?;
+        state.serialize_field("seq", &self.seq)?;
+        state.serialize_field("elapsed_ns", &self.elapsed_ns)?;
+        state.serialize_field("user_token", &self.user_token)?;
         state.end()
     }
 }
@@ -121,17 +143,50 @@ impl Subscribable for ConnFeatures {
 #[doc(hidden)]
 pub struct TrackedConnFeatures {
     sni: String,
+    five_tuple: FiveTuple,
     ctos: FlowFeatures,
     stoc: FlowFeatures,
+    /// Number of feature records already reported for this connection (snapshots plus, at
+    /// termination, the final record), used to tag each one with `ConnFeatures::seq`.
+    seq: u32,
+    /// Opaque correlation token, carried into every `ConnFeatures` this connection reports.
+    user_token: u64,
+    /// User-attached per-connection state, e.g. a running counter or a model handle, read and
+    /// mutated from `on_match`/`post_match`/`on_terminate` via `user_state`.
+    user_state: UserState,
+    /// TSC at the last intermediate snapshot, or at connection creation if none has fired yet.
+    last_snapshot_tsc: u64,
+}
+
+/// Type-erased, lazily-initialized per-connection slot for user state.
+///
+/// `TrackedConnFeatures` isn't generic over the state type, so instead of a type parameter this
+/// stores the state behind `dyn Any` and downcasts on access, initializing to `T::default()` the
+/// first time a given type is requested. Picking a different `T` than was previously stored
+/// replaces (and loses) whatever was there, so callers should settle on one state type per
+/// subscription.
+struct UserState(Box<dyn Any + Send>);
+
+impl UserState {
+    fn empty() -> Self {
+        UserState(Box::new(()))
+    }
+
+    fn get_mut<T: Default + Send + 'static>(&mut self) -> &mut T {
+        if !self.0.is::<T>() {
+            self.0 = Box::new(T::default());
+        }
+        self.0.downcast_mut::<T>().unwrap()
+    }
 }
 
 impl TrackedConnFeatures {
     #[inline]
     fn update(&mut self, segment: L4Pdu) {
         if segment.dir {
-            self.ctos.insert_segment(segment);
+            self.ctos.insert_segment(segment, &self.five_tuple);
         } else {
-            self.stoc.insert_segment(segment);
+            self.stoc.insert_segment(segment, &self.five_tuple);
         }
     }
 
@@ -144,16 +199,91 @@ impl TrackedConnFeatures {
         self.stoc.extract_features(&mut features);
         features
     }
+
+    /// Time elapsed since the connection began, in nanoseconds.
+    #[inline]
+    fn elapsed_ns(&self) -> u64 {
+        ((unsafe { rte_rdtsc() }.saturating_sub(self.ctos.start_tsc)) as f64 / *TSC_HZ * 1e9)
+            as u64
+    }
+
+    /// Reports the features accumulated so far, tagged with a sequence number and the
+    /// connection's elapsed time, without waiting for `on_terminate`.
+    ///
+    /// This lets a long-lived connection (e.g. a multi-minute exfiltration flow) be acted on
+    /// while still in progress, instead of only once at termination. Driven automatically by
+    /// `maybe_snapshot` every `SNAPSHOT_INTERVAL_NS`; call directly only to force an out-of-band
+    /// report.
+    fn snapshot(&mut self, subscription: &Subscription<<Self as Trackable>::Subscribed>) {
+        self.seq += 1;
+        let conn = ConnFeatures {
+            sni: self.sni.clone(),
+            features: self.extract_features(),
+            seq: self.seq,
+            elapsed_ns: self.elapsed_ns(),
+            user_token: self.user_token,
+        };
+        subscription.invoke(conn);
+    }
+
+    /// Emits an intermediate `snapshot` if at least `SNAPSHOT_INTERVAL_NS` has elapsed since the
+    /// last one (or since the connection began, if none has fired yet), then `reset_window`s so
+    /// each snapshot reports only the traffic seen since the previous one rather than
+    /// accumulating over the whole connection. Called from `post_match`, the only per-packet point
+    /// in this type's `Trackable` lifecycle with `Subscription` access.
+    fn maybe_snapshot(&mut self, subscription: &Subscription<<Self as Trackable>::Subscribed>) {
+        let now_tsc = unsafe { rte_rdtsc() };
+        let since_last_ns = now_tsc.saturating_sub(self.last_snapshot_tsc) as f64 / *TSC_HZ * 1e9;
+        if since_last_ns >= SNAPSHOT_INTERVAL_NS as f64 {
+            self.last_snapshot_tsc = now_tsc;
+            self.snapshot(subscription);
+            self.reset_window();
+        }
+    }
+
+    /// Clears the accumulated per-flow statistics so that the next `snapshot` or `on_terminate`
+    /// reports features over only the packets seen since the last reset, rather than
+    /// cumulatively over the whole connection. This also zeroes each flow's `packet_cnt`, so
+    /// `early_terminate` counts only the packets seen since the last reset.
+    fn reset_window(&mut self) {
+        self.ctos.reset_window();
+        self.stoc.reset_window();
+    }
+
+    /// Returns a mutable reference to this connection's user-attached state, initializing it to
+    /// `T::default()` on first access. Use from `on_match`/`post_match`/`on_terminate` to
+    /// maintain counters, model handles, ring buffers, or other stateful logic without reaching
+    /// for a process-wide `Mutex`.
+    pub fn user_state<T: Default + Send + 'static>(&mut self) -> &mut T {
+        self.user_state.get_mut()
+    }
+
+    /// Opaque correlation token previously set via `set_user_token`, `0` if never set. Carried
+    /// into every `ConnFeatures` this connection reports.
+    pub fn user_token(&self) -> u64 {
+        self.user_token
+    }
+
+    /// Attaches an opaque correlation token to this connection, to be read back via
+    /// `user_token` and carried into reported `ConnFeatures`.
+    pub fn set_user_token(&mut self, token: u64) {
+        self.user_token = token;
+    }
 }
 
 impl Trackable for TrackedConnFeatures {
     type Subscribed = ConnFeatures;
 
-    fn new(_five_tuple: FiveTuple) -> Self {
+    fn new(five_tuple: FiveTuple) -> Self {
         TrackedConnFeatures {
             sni: String::new(),
+            five_tuple,
             ctos: FlowFeatures::new(),
             stoc: FlowFeatures::new(),
+            seq: 0,
+            user_token: 0,
+            user_state: UserState::empty(),
+            last_snapshot_tsc: unsafe { rte_rdtsc() },
         }
     }
 
@@ -167,14 +297,19 @@ impl Trackable for TrackedConnFeatures {
         }
     }
 
-    fn post_match(&mut self, pdu: L4Pdu, _subscription: &Subscription<Self::Subscribed>) {
-        self.update(pdu)
+    fn post_match(&mut self, pdu: L4Pdu, subscription: &Subscription<Self::Subscribed>) {
+        self.update(pdu);
+        self.maybe_snapshot(subscription);
     }
 
     fn on_terminate(&mut self, subscription: &Subscription<Self::Subscribed>) {
+        self.seq += 1;
         let conn = ConnFeatures {
             sni: self.sni.clone(),
             features: self.extract_features(),
+            seq: self.seq,
+            elapsed_ns: self.elapsed_ns(),
+            user_token: self.user_token,
         };
         subscription.invoke(conn);
     }
@@ -184,75 +319,200 @@ impl Trackable for TrackedConnFeatures {
     }
 }
 
+/// Single-pass summary statistics for one tracked quantity: Welford's online variance, min/max,
+/// and P² estimates of p25/p50/p75/p90, none of which require storing the observations.
+#[derive(Debug, Clone)]
+struct OnlineStats {
+    n: f64,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    p25: P2Quantile,
+    p50: P2Quantile,
+    p75: P2Quantile,
+    p90: P2Quantile,
+}
+
+impl OnlineStats {
+    fn new() -> Self {
+        OnlineStats {
+            n: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            p25: P2Quantile::new(0.25),
+            p50: P2Quantile::new(0.5),
+            p75: P2Quantile::new(0.75),
+            p90: P2Quantile::new(0.9),
+        }
+    }
+
+    fn update(&mut self, x: f64) {
+        self.n += 1.0;
+        let delta = x - self.mean;
+        self.mean += delta / self.n;
+        let delta2 = x - self.mean;
+        self.m2 += delta * delta2;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.p25.update(x);
+        self.p50.update(x);
+        self.p75.update(x);
+        self.p90.update(x);
+    }
+
+    fn variance(&self) -> f64 {
+        if self.n < 1.0 {
+            0.0
+        } else {
+            self.m2 / self.n
+        }
+    }
+
+    /// Appends mean, stddev, min, max, p25, p50, p75, p90, in that order. All are `0.0` if no
+    /// observations have been seen, except the percentiles, which are `NAN`.
+    fn extract_features(&self, features: &mut Vec<f64>) {
+        let (min, max) = if self.n == 0.0 {
+            (0.0, 0.0)
+        } else {
+            (self.min, self.max)
+        };
+        features.extend_from_slice(&[
+            self.mean,
+            self.variance().sqrt(),
+            min,
+            max,
+            self.p25.value(),
+            self.p50.value(),
+            self.p75.value(),
+            self.p90.value(),
+        ]);
+    }
+}
+
 /// A uni-directional flow.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone)]
 pub struct FlowFeatures {
     /// connection start timestamp
-    pub start_tsc: u32,
-    /// time offset from start of connection in ns
-    pub delta_ns: Vec<u32>,
+    pub start_tsc: u64,
     /// number of packets observed in flow
     pub packet_cnt: u32,
-    /// sum of IP packet lengths
-    pub ip_total_length: u32,
-    /// sum of TCP window sizes
-    pub tcp_window_size: u32,
+    /// time offset from start of connection in ns of the last observed packet, used to derive
+    /// inter-arrival time without keeping the full `delta_ns` history around
+    last_delta_ns: Option<u32>,
+    pktsize: OnlineStats,
+    winsize: OnlineStats,
+    iat: OnlineStats,
 }
 
 impl FlowFeatures {
     fn new() -> Self {
         FlowFeatures {
-            start_tsc: unsafe { rte_rdtsc() } as u32,
-            delta_ns: vec![],
+            start_tsc: unsafe { rte_rdtsc() },
             packet_cnt: 0,
-            ip_total_length: 0,
-            tcp_window_size: 0,
+            last_delta_ns: None,
+            pktsize: OnlineStats::new(),
+            winsize: OnlineStats::new(),
+            iat: OnlineStats::new(),
         }
     }
 
     #[inline]
-    fn insert_segment(&mut self, segment: L4Pdu) {
+    #[cfg_attr(not(feature = "conn-trace"), allow(unused_variables))]
+    fn insert_segment(&mut self, segment: L4Pdu, five_tuple: &FiveTuple) {
+        let dir = segment.dir;
         let mbuf = segment.mbuf_ref();
         if let Ok(eth) = mbuf.parse_to::<Ethernet>() {
             if let Ok(ipv4) = eth.parse_to::<Ipv4>() {
                 if let Ok(tcp) = ipv4.parse_to::<Tcp>() {
-                    let curr_tsc = unsafe { rte_rdtsc() } as u32;
+                    let curr_tsc = unsafe { rte_rdtsc() };
                     let delta_ns =
                         ((curr_tsc.saturating_sub(self.start_tsc)) as f64 / *TSC_HZ * 1e9) as u32;
-                    self.delta_ns.push(delta_ns);
+                    if let Some(last_delta_ns) = self.last_delta_ns {
+                        self.iat.update(delta_ns.saturating_sub(last_delta_ns) as f64);
+                    }
+                    self.last_delta_ns = Some(delta_ns);
                     self.packet_cnt += 1;
-                    self.ip_total_length += ipv4.total_length() as u32;
-                    self.tcp_window_size += tcp.window() as u32;
+                    self.pktsize.update(ipv4.total_length() as f64);
+                    self.winsize.update(tcp.window() as f64);
+
+                    #[cfg(feature = "conn-trace")]
+                    {
+                        let transition = if tcp.syn() && tcp.ack() {
+                            "SYN-ACK"
+                        } else if tcp.syn() {
+                            "SYN"
+                        } else if tcp.ack() {
+                            "ACK"
+                        } else {
+                            "DATA"
+                        };
+                        trace!(
+                            "{} dir={} ts={} ip_len={} tcp_syn={} tcp_ack={} tcp_fin={} tcp_rst={} tcp_win={} transition={}",
+                            five_tuple,
+                            dir,
+                            delta_ns,
+                            ipv4.total_length(),
+                            tcp.syn(),
+                            tcp.ack(),
+                            tcp.fin(),
+                            tcp.rst(),
+                            tcp.window(),
+                            transition
+                        );
+                    }
                 }
             }
         }
     }
 
     fn extract_features(&self, features: &mut Vec<f64>) {
-        if self.packet_cnt == 0 {
-            features.push(0.0); // packet count
-            features.push(0.0); // mean packet size (bytes)
-            features.push(0.0); // mean window size (window-size units)
-            features.push(0.0); // mean inter-arrival time (ns)
-        } else {
-            let pktsize_mean = self.ip_total_length as f64 / self.packet_cnt as f64;
-            let winsize_mean = self.tcp_window_size as f64 / self.packet_cnt as f64;
-
-            let mut iat_sum = 0.0;
-            let mut cnt = 0;
-            for i in 1..self.delta_ns.len() {
-                iat_sum += (self.delta_ns[i] - self.delta_ns[i - 1]) as f64;
-                cnt += 1;
-            }
+        features.push(self.packet_cnt as f64);
+        self.pktsize.extract_features(features);
+        self.winsize.extract_features(features);
+        self.iat.extract_features(features);
+    }
 
-            let iat_mean = if cnt > 0 { iat_sum / (cnt as f64) } else { 0.0 };
+    /// Clears accumulated statistics so subsequent features are computed over a fresh window
+    /// rather than cumulatively. `start_tsc` is left untouched, since it anchors the
+    /// connection's overall elapsed time, not this flow's current window.
+    fn reset_window(&mut self) {
+        self.packet_cnt = 0;
+        self.last_delta_ns = None;
+        self.pktsize = OnlineStats::new();
+        self.winsize = OnlineStats::new();
+        self.iat = OnlineStats::new();
+    }
+}
 
-            features.extend_from_slice(&[
-                self.packet_cnt as f64,
-                pktsize_mean,
-                winsize_mean,
-                iat_mean,
-            ]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn online_stats_tracks_mean_min_max() {
+        let mut stats = OnlineStats::new();
+        for x in [1.0, 2.0, 3.0, 4.0, 5.0] {
+            stats.update(x);
         }
+        let mut features = Vec::new();
+        stats.extract_features(&mut features);
+        assert_eq!(features[0], 3.0); // mean
+        assert_eq!(features[2], 1.0); // min
+        assert_eq!(features[3], 5.0); // max
+    }
+
+    #[test]
+    fn online_stats_with_no_observations_is_zero_except_percentiles() {
+        let stats = OnlineStats::new();
+        let mut features = Vec::new();
+        stats.extract_features(&mut features);
+        assert_eq!(features[0], 0.0); // mean
+        assert_eq!(features[1], 0.0); // stddev
+        assert_eq!(features[2], 0.0); // min
+        assert_eq!(features[3], 0.0); // max
+        assert!(features[4].is_nan()); // p25
     }
 }
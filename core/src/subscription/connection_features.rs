@@ -21,21 +21,21 @@ use crate::filter::FilterResult;
 use crate::memory::mbuf::Mbuf;
 use crate::protocols::packet::ethernet::Ethernet;
 use crate::protocols::packet::ipv4::Ipv4;
-use crate::protocols::packet::tcp::Tcp;
 use crate::protocols::packet::Packet;
 use crate::protocols::stream::{ConnParser, Session, SessionData};
+use crate::subscription::l3::L3Hdr;
+use crate::subscription::quantile::P2Quantile;
 use crate::subscription::{Level, Subscribable, Subscription, Trackable};
 
 use std::fmt;
 use std::collections::HashSet;
 use std::collections::HashMap;
-use std::ops::Index;
 
-use anyhow::Result;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
-use statrs::statistics::{Min, Max, OrderStatistics, Distribution};
-use statrs::statistics::Data;
+
+#[cfg(feature = "feature-trace")]
+use log::trace;
 
 use lazy_static::lazy_static;
 
@@ -57,10 +57,21 @@ pub struct ConnectionFeatures {
     pub orig: FlowFeatures,
     /// Responder flow features.
     pub resp: FlowFeatures,
+    /// Mean duration (ns) of an active burst, i.e. a run of packets with no inter-arrival gap
+    /// exceeding `ACTIVE_TIMEOUT_NS`, in either direction.
+    pub active_mean: f64,
+    pub active_std: f64,
+    pub active_min: f64,
+    pub active_max: f64,
+    /// Mean duration (ns) of an idle gap between active bursts.
+    pub idle_mean: f64,
+    pub idle_std: f64,
+    pub idle_min: f64,
+    pub idle_max: f64,
 }
 
 impl ConnectionFeatures {
-    
+
 }
 
 impl Serialize for ConnectionFeatures {
@@ -68,10 +79,18 @@ impl Serialize for ConnectionFeatures {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("ConnectionFeatures", 3)?;
+        let mut state = serializer.serialize_struct("ConnectionFeatures", 11)?;
         state.serialize_field("sni", &self.sni)?;
         state.serialize_field("orig", &self.orig)?;
         state.serialize_field("resp", &self.resp)?;
+        state.serialize_field("active_mean", &self.active_mean)?;
+        state.serialize_field("active_std", &self.active_std)?;
+        state.serialize_field("active_min", &self.active_min)?;
+        state.serialize_field("active_max", &self.active_max)?;
+        state.serialize_field("idle_mean", &self.idle_mean)?;
+        state.serialize_field("idle_std", &self.idle_std)?;
+        state.serialize_field("idle_min", &self.idle_min)?;
+        state.serialize_field("idle_max", &self.idle_max)?;
         state.end()
     }
 }
@@ -115,6 +134,15 @@ impl Subscribable for ConnectionFeatures {
     }
 }
 
+/// Inter-arrival gap, in nanoseconds, beyond which a connection is considered to have left one
+/// "active" burst and entered an "idle" period. Mirrors the default flow timeout used by
+/// CICFlowMeter-style tools.
+const ACTIVE_TIMEOUT_NS: u64 = 5_000_000_000;
+
+/// Idle duration, in nanoseconds, beyond which a connection is presumed dead and should be
+/// flushed and evicted rather than left to linger in the tracker table.
+const IDLE_HARD_TIMEOUT_NS: u64 = 120_000_000_000;
+
 /// Tracks a connection record throughout its lifetime.
 ///
 /// ## Note
@@ -122,18 +150,44 @@ impl Subscribable for ConnectionFeatures {
 /// public. Documentation is hidden by default to avoid confusing users.
 #[doc(hidden)]
 pub struct TrackedConnectionFeatures {
+    five_tuple: FiveTuple,
     sni: String,
     ctos: FlowFeatures,
     stoc: FlowFeatures,
+    /// TSC of the most recently seen packet in either direction.
+    last_pkt_tsc: Option<u64>,
+    /// TSC at which the current active burst began.
+    active_start_tsc: u64,
+    active: OnlineAgg,
+    idle: OnlineAgg,
 }
 
 impl TrackedConnectionFeatures {
     #[inline]
     fn update(&mut self, segment: L4Pdu) {
-        if segment.dir {
-            self.ctos.insert_segment(segment);
+        let curr_tsc = unsafe { rte_rdtsc() };
+        match self.last_pkt_tsc {
+            None => {
+                self.active_start_tsc = curr_tsc;
+            }
+            Some(last_tsc) => {
+                let gap_ns = curr_tsc.saturating_sub(last_tsc) as f64 / *TSC_HZ * 1e9;
+                if gap_ns > ACTIVE_TIMEOUT_NS as f64 {
+                    let active_ns =
+                        last_tsc.saturating_sub(self.active_start_tsc) as f64 / *TSC_HZ * 1e9;
+                    self.active.update(active_ns as u32);
+                    self.idle.update(gap_ns as u32);
+                    self.active_start_tsc = curr_tsc;
+                }
+            }
+        }
+        self.last_pkt_tsc = Some(curr_tsc);
+
+        let dir = segment.dir;
+        if dir {
+            self.ctos.insert_segment(segment, &self.five_tuple, dir);
         } else {
-            self.stoc.insert_segment(segment);
+            self.stoc.insert_segment(segment, &self.five_tuple, dir);
         }
     }
 }
@@ -141,11 +195,16 @@ impl TrackedConnectionFeatures {
 impl Trackable for TrackedConnectionFeatures {
     type Subscribed = ConnectionFeatures;
 
-    fn new(_five_tuple: FiveTuple) -> Self {
+    fn new(five_tuple: FiveTuple) -> Self {
         TrackedConnectionFeatures {
+            five_tuple,
             sni: String::new(),
             ctos: FlowFeatures::new(),
             stoc: FlowFeatures::new(),
+            last_pkt_tsc: None,
+            active_start_tsc: 0,
+            active: OnlineAgg::new(),
+            idle: OnlineAgg::new(),
         }
     }
 
@@ -164,29 +223,310 @@ impl Trackable for TrackedConnectionFeatures {
     }
 
     fn on_terminate(&mut self, subscription: &Subscription<Self::Subscribed>) {
+        // Close out the trailing active burst, if any packets arrived since the last idle gap.
+        if let Some(last_tsc) = self.last_pkt_tsc {
+            let active_ns =
+                last_tsc.saturating_sub(self.active_start_tsc) as f64 / *TSC_HZ * 1e9;
+            self.active.update(active_ns as u32);
+        }
+
         let conn = ConnectionFeatures {
             sni: self.sni.clone(),
             orig: self.ctos.clone(),
             resp: self.stoc.clone(),
+            active_mean: self.active.aggregate("mean"),
+            active_std: self.active.aggregate("std"),
+            active_min: self.active.aggregate("min"),
+            active_max: self.active.aggregate("max"),
+            idle_mean: self.idle.aggregate("mean"),
+            idle_std: self.idle.aggregate("std"),
+            idle_min: self.idle.aggregate("min"),
+            idle_max: self.idle.aggregate("max"),
         };
         subscription.invoke(conn);
     }
+
+    fn early_terminate(&self) -> bool {
+        match self.last_pkt_tsc {
+            Some(last_tsc) => {
+                let curr_tsc = unsafe { rte_rdtsc() };
+                let idle_ns = curr_tsc.saturating_sub(last_tsc) as f64 / *TSC_HZ * 1e9;
+                idle_ns > IDLE_HARD_TIMEOUT_NS as f64
+            }
+            None => false,
+        }
+    }
+}
+
+/// Bounds the number of distinct values tracked for the `dist` aggregate, so that high-
+/// cardinality classes (e.g. sequence numbers) don't grow memory unboundedly.
+const DIST_CAP: usize = 1024;
+
+/// Online accumulators for a single feature class, replacing a per-packet `Vec<u32>` so that
+/// memory use is O(1) rather than growing with the life of the flow. Mean/variance/skewness/
+/// kurtosis use Welford/Terriberry's single-pass central-moment recurrences; q1/med/q3 use a
+/// `P2Quantile` per quantile; min/max are tracked exactly; `dist` is a bounded distinct-value
+/// count.
+#[derive(Debug, Clone)]
+struct OnlineAgg {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    m3: f64,
+    m4: f64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: Option<f64>,
+    q1: P2Quantile,
+    med: P2Quantile,
+    q3: P2Quantile,
+    dist: HashSet<u32>,
+}
+
+impl OnlineAgg {
+    fn new() -> Self {
+        OnlineAgg {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            first: None,
+            q1: P2Quantile::new(0.25),
+            med: P2Quantile::new(0.5),
+            q3: P2Quantile::new(0.75),
+            dist: HashSet::new(),
+        }
+    }
+
+    fn update(&mut self, x: u32) {
+        let xf = x as f64;
+        if self.first.is_none() {
+            self.first = Some(xf);
+        }
+        let n1 = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = xf - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term1 = delta * delta_n * n1;
+        self.mean += delta_n;
+        self.m4 += term1 * delta_n2 * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n2 * self.m2
+            - 4.0 * delta_n * self.m3;
+        self.m3 += term1 * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2;
+        self.m2 += term1;
+        self.sum += xf;
+        self.min = self.min.min(xf);
+        self.max = self.max.max(xf);
+        self.q1.update(xf);
+        self.med.update(xf);
+        self.q3.update(xf);
+        if self.dist.len() < DIST_CAP {
+            self.dist.insert(x);
+        }
+    }
+
+    fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    fn skewness(&self) -> f64 {
+        if self.count < 2 || self.m2 == 0.0 {
+            0.0
+        } else {
+            (self.count as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+        }
+    }
+
+    fn kurtosis(&self) -> f64 {
+        if self.count < 2 || self.m2 == 0.0 {
+            0.0
+        } else {
+            self.count as f64 * self.m4 / (self.m2 * self.m2) - 3.0
+        }
+    }
+
+    fn aggregate(&self, agg: &str) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        match agg {
+            "min" => self.min,
+            "q1" => self.q1.value(),
+            "med" => self.med.value(),
+            "q3" => self.q3.value(),
+            "max" => self.max,
+            "mean" => self.mean,
+            "std" => self.variance().sqrt(),
+            "skew" => self.skewness(),
+            "kurt" => self.kurtosis(),
+            "sum" => self.sum,
+            "dist" => self.dist.len() as f64,
+            "first" => self.first.unwrap_or(0.0),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Per-packet feature values captured from a fragment, keyed by the same names as
+/// `PACKET_FT_CLASSES`, held until an IPv4 datagram is fully reassembled.
+type FragSnapshot = HashMap<&'static str, u32>;
+
+/// Identifies the IPv4 datagram a fragment belongs to, per RFC 791 (source, destination,
+/// identification, protocol).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FragKey {
+    src: u32,
+    dst: u32,
+    id: u16,
+    protocol: u8,
+}
+
+/// In-flight fragments of one IPv4 datagram.
+#[derive(Debug, Clone)]
+struct FragEntry {
+    /// `(offset, length)` in bytes of each fragment seen so far.
+    ranges: Vec<(u32, u32)>,
+    /// Total reassembled datagram length, known once the trailing (MF=0) fragment arrives.
+    total_len: Option<u32>,
+    /// Feature values captured from the lead (offset 0) fragment, which alone carries the
+    /// transport header.
+    snapshot: Option<FragSnapshot>,
+    last_seen_tsc: u32,
+}
+
+/// Maximum number of IPv4 datagrams that may be mid-reassembly at once, so that spoofed or lost
+/// fragments can't grow memory without bound.
+const FRAG_CAP: usize = 256;
+
+/// Per-datagram reassembly timeout, in nanoseconds. An incomplete fragment set older than this is
+/// discarded rather than held indefinitely.
+const FRAG_TIMEOUT_NS: u64 = 30_000_000_000;
+
+/// Buffers IPv4 fragments, keyed by (src, dst, id, protocol), and reassembles them into a single
+/// logical packet once every fragment up to the trailing (MF=0) one has arrived contiguously.
+/// Modeled on smoltcp's `iface/fragmentation` reassembly buffer.
+#[derive(Debug, Clone)]
+struct FragReassembler {
+    entries: HashMap<FragKey, FragEntry>,
+}
+
+impl FragReassembler {
+    fn new() -> Self {
+        FragReassembler {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn evict_stale(&mut self, now_tsc: u32) {
+        self.entries.retain(|_, entry| {
+            let age_ns = now_tsc.wrapping_sub(entry.last_seen_tsc) as f64 / *TSC_HZ * 1e9;
+            age_ns <= FRAG_TIMEOUT_NS as f64
+        });
+    }
+
+    fn is_contiguous(ranges: &[(u32, u32)], total_len: u32) -> bool {
+        let mut sorted = ranges.to_vec();
+        sorted.sort_by_key(|&(offset, _)| offset);
+        let mut covered = 0u32;
+        for (offset, length) in sorted {
+            if offset > covered {
+                return false;
+            }
+            covered = covered.max(offset + length);
+        }
+        covered >= total_len
+    }
+
+    /// Buffers one fragment, returning the completed datagram's feature snapshot (with
+    /// `ip_total_length` set to the reassembled length) once reassembly is complete.
+    fn insert(
+        &mut self,
+        key: FragKey,
+        offset: u32,
+        length: u32,
+        more_fragments: bool,
+        lead_snapshot: Option<FragSnapshot>,
+        now_tsc: u32,
+    ) -> Option<FragSnapshot> {
+        self.evict_stale(now_tsc);
+        if !self.entries.contains_key(&key) && self.entries.len() >= FRAG_CAP {
+            // Bounded buffer is full; drop the fragment rather than let the set grow unbounded.
+            return None;
+        }
+        let entry = self.entries.entry(key.clone()).or_insert_with(|| FragEntry {
+            ranges: vec![],
+            total_len: None,
+            snapshot: None,
+            last_seen_tsc: now_tsc,
+        });
+        entry.last_seen_tsc = now_tsc;
+        entry.ranges.push((offset, length));
+        if !more_fragments {
+            entry.total_len = Some(offset + length);
+        }
+        if lead_snapshot.is_some() {
+            entry.snapshot = lead_snapshot;
+        }
+
+        if let Some(total_len) = entry.total_len {
+            if Self::is_contiguous(&entry.ranges, total_len) {
+                if let Some(entry) = self.entries.remove(&key) {
+                    if let Some(mut snapshot) = entry.snapshot {
+                        // `total_len` is the summed payload length across fragments; the lead
+                        // fragment's `ip_ihl` (captured in `snapshot`) gives the one IP header
+                        // to add back so this matches `L3Hdr::total_length()` for unfragmented
+                        // packets, which counts the header once.
+                        let header_len = snapshot.get("ip_ihl").copied().unwrap_or(0) * 4;
+                        snapshot.insert("ip_total_length", total_len + header_len);
+                        return Some(snapshot);
+                    }
+                }
+            }
+        }
+        None
+    }
 }
 
 /// A uni-directional flow.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone)]
 pub struct FlowFeatures {
     pub start_tsc: u32,
     pub packet_cnt: u32,
     pub byte_cnt: u32,
-    pub pkt_data: HashMap<&'static str, Vec<u32>>,
+    pkt_stats: HashMap<&'static str, OnlineAgg>,
+    frag_reassembler: FragReassembler,
+}
+
+impl Serialize for FlowFeatures {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("FlowFeatures", 4)?;
+        state.serialize_field("start_tsc", &self.start_tsc)?;
+        state.serialize_field("packet_cnt", &self.packet_cnt)?;
+        state.serialize_field("byte_cnt", &self.byte_cnt)?;
+        state.serialize_field("features", &self.get_features())?;
+        state.end()
+    }
 }
 
-const PACKET_FT_CLASSES: [&'static str; 30] = [
+const PACKET_FT_CLASSES: [&'static str; 35] = [
     "packet_iat",
     "ip_ihl",
     "ip_dscp",
     "ip_ecn",
+    "ip_flow_label",
     "ip_total_length",
     "ip_id",
     "ip_flags_rf",
@@ -213,6 +553,10 @@ const PACKET_FT_CLASSES: [&'static str; 30] = [
     "tcp_window_size",
     "tcp_checksum",
     "tcp_urgent_ptr",
+    "udp_src_port",
+    "udp_dst_port",
+    "udp_length",
+    "udp_checksum",
 ];
 
 const FLOW_FT_CLASSES: [&'static str; 4] = [
@@ -243,95 +587,337 @@ impl FlowFeatures {
             start_tsc: unsafe { rte_rdtsc() } as u32,
             packet_cnt: 0,
             byte_cnt: 0,
-            pkt_data: PACKET_FT_CLASSES.into_iter().map(|key| (key, vec![])).collect(),
+            pkt_stats: PACKET_FT_CLASSES
+                .into_iter()
+                .map(|key| (key, OnlineAgg::new()))
+                .collect(),
+            frag_reassembler: FragReassembler::new(),
         }
     }
 
-    fn get_features(&self, n_pkts: Option<usize>) -> Vec<f64> {
+    /// Flattens `pkt_stats` into the same `{ft_class}_{agg}`-ordered feature vector the old
+    /// `Vec`-backed implementation produced, now computed from O(1) per-class state.
+    fn get_features(&self) -> Vec<f64> {
         let mut features: Vec<f64> = vec![];
-        
         for ft_class in PACKET_FT_CLASSES.iter() {
-            // if ft_class == "packet_iat" {
-            //     let raw_features = get_iat()
-            // }
-            let raw_features = self.pkt_data.get(ft_class).unwrap();
-            let n_pkts = n_pkts.unwrap_or(raw_features.len());
+            let stats = self.pkt_stats.get(ft_class).unwrap();
             for agg in AGGREGATORS.iter() {
-                let key = format!("{ft_class}_{agg}");
-                let value = aggregate(agg, &raw_features[0..n_pkts]);
-                features.push(value);
+                features.push(stats.aggregate(agg));
             }
         }
         features
     }
 
     #[inline]
-    fn insert_segment(&mut self, segment: L4Pdu) {
+    fn insert_segment(&mut self, segment: L4Pdu, five_tuple: &FiveTuple, dir: bool) {
         let mbuf = segment.mbuf_ref();
         if let Ok(eth) = mbuf.parse_to::<Ethernet>() {
             let curr_tsc = unsafe { rte_rdtsc() } as u32;
-            let delta_ns = ((curr_tsc - self.start_tsc) as f64 / *TSC_HZ * 1e9) as u32;
-            self.pkt_data.get_mut("delta_ns").unwrap().push(delta_ns);
-            if let Ok(ipv4) = eth.parse_to::<Ipv4>() {
-                self.packet_cnt += 1;
-                self.byte_cnt += ipv4.total_length() as u32;
-                self.pkt_data.get_mut("ip_ihl").unwrap().push(ipv4.ihl().into());
-                self.pkt_data.get_mut("ip_dscp").unwrap().push(ipv4.dscp().into());
-                self.pkt_data.get_mut("ip_ecn").unwrap().push(ipv4.ecn().into());
-                self.pkt_data.get_mut("ip_total_length").unwrap().push(ipv4.total_length().into());
-                self.pkt_data.get_mut("ip_id").unwrap().push(ipv4.identification().into());
-                self.pkt_data.get_mut("ip_flags_rf").unwrap().push(ipv4.rf().into());
-                self.pkt_data.get_mut("ip_flags_df").unwrap().push(ipv4.df().into());
-                self.pkt_data.get_mut("ip_flags_mf").unwrap().push(ipv4.mf().into());
-                self.pkt_data.get_mut("ip_fragment_offset").unwrap().push(ipv4.fragment_offset().into());
-                self.pkt_data.get_mut("ip_ttl").unwrap().push(ipv4.time_to_live().into());
-                self.pkt_data.get_mut("ip_protocol").unwrap().push(ipv4.protocol().into());
-                self.pkt_data.get_mut("ip_header_checksum").unwrap().push(ipv4.header_checksum().into());
-                if let Ok(tcp) = ipv4.parse_to::<Tcp>() {
-                    self.pkt_data.get_mut("tcp_src_port").unwrap().push(tcp.src_port().into());
-                    self.pkt_data.get_mut("tcp_dst_port").unwrap().push(tcp.dst_port().into());
-                    self.pkt_data.get_mut("tcp_seq_num").unwrap().push(tcp.seq_no().into());
-                    self.pkt_data.get_mut("tcp_ack_num").unwrap().push(tcp.ack_no().into());
-                    self.pkt_data.get_mut("tcp_data_offset").unwrap().push(tcp.data_offset().into());
-                    self.pkt_data.get_mut("tcp_reserved").unwrap().push(tcp.reserved().into());
-                    self.pkt_data.get_mut("tcp_flags_cwr").unwrap().push(tcp.cwr().into());
-                    self.pkt_data.get_mut("tcp_flags_ece").unwrap().push(tcp.ece().into());
-                    self.pkt_data.get_mut("tcp_flags_urg").unwrap().push(tcp.urg().into());
-                    self.pkt_data.get_mut("tcp_flags_ack").unwrap().push(tcp.ack().into());
-                    self.pkt_data.get_mut("tcp_flags_psh").unwrap().push(tcp.psh().into());
-                    self.pkt_data.get_mut("tcp_flags_rst").unwrap().push(tcp.rst().into());
-                    self.pkt_data.get_mut("tcp_flags_syn").unwrap().push(tcp.syn().into());
-                    self.pkt_data.get_mut("tcp_flags_fin").unwrap().push(tcp.fin().into());
-                    self.pkt_data.get_mut("tcp_window_size").unwrap().push(tcp.window().into());
-                    self.pkt_data.get_mut("tcp_checksum").unwrap().push(tcp.checksum().into());
-                    self.pkt_data.get_mut("tcp_urgent_ptr").unwrap().push(tcp.urgent_pointer().into());
+            if let Ok(l3) = L3Hdr::parse(eth) {
+                if let L3Hdr::V4(ipv4) = &l3 {
+                    if ipv4.mf() || ipv4.fragment_offset() != 0 {
+                        self.insert_fragment(&l3, ipv4, curr_tsc, five_tuple, dir);
+                        return;
+                    }
                 }
+                self.record_packet(&l3, curr_tsc, five_tuple, dir);
+            }
+        }
+    }
+
+    /// Records an unfragmented (or already-reassembled) datagram as a single logical packet.
+    #[cfg_attr(not(feature = "feature-trace"), allow(unused_variables))]
+    fn record_packet(&mut self, l3: &L3Hdr, curr_tsc: u32, five_tuple: &FiveTuple, dir: bool) {
+        let delta_ns = ((curr_tsc - self.start_tsc) as f64 / *TSC_HZ * 1e9) as u32;
+        self.pkt_stats.get_mut("packet_iat").unwrap().update(delta_ns);
+        self.packet_cnt += 1;
+        self.byte_cnt += l3.total_length();
+        self.pkt_stats.get_mut("ip_dscp").unwrap().update(l3.dscp());
+        self.pkt_stats.get_mut("ip_ecn").unwrap().update(l3.ecn());
+        self.pkt_stats.get_mut("ip_flow_label").unwrap().update(l3.flow_label());
+        self.pkt_stats.get_mut("ip_total_length").unwrap().update(l3.total_length());
+        self.pkt_stats.get_mut("ip_ttl").unwrap().update(l3.ttl());
+        self.pkt_stats.get_mut("ip_protocol").unwrap().update(l3.protocol());
+        if let L3Hdr::V4(ipv4) = l3 {
+            self.pkt_stats.get_mut("ip_ihl").unwrap().update(ipv4.ihl().into());
+            self.pkt_stats.get_mut("ip_id").unwrap().update(ipv4.identification().into());
+            self.pkt_stats.get_mut("ip_flags_rf").unwrap().update(ipv4.rf().into());
+            self.pkt_stats.get_mut("ip_flags_df").unwrap().update(ipv4.df().into());
+            self.pkt_stats.get_mut("ip_flags_mf").unwrap().update(ipv4.mf().into());
+            self.pkt_stats.get_mut("ip_fragment_offset").unwrap().update(ipv4.fragment_offset().into());
+            self.pkt_stats.get_mut("ip_header_checksum").unwrap().update(ipv4.header_checksum().into());
+        }
+        if let Ok(tcp) = l3.parse_to_tcp() {
+            self.pkt_stats.get_mut("tcp_src_port").unwrap().update(tcp.src_port().into());
+            self.pkt_stats.get_mut("tcp_dst_port").unwrap().update(tcp.dst_port().into());
+            self.pkt_stats.get_mut("tcp_seq_num").unwrap().update(tcp.seq_no().into());
+            self.pkt_stats.get_mut("tcp_ack_num").unwrap().update(tcp.ack_no().into());
+            self.pkt_stats.get_mut("tcp_data_offset").unwrap().update(tcp.data_offset().into());
+            self.pkt_stats.get_mut("tcp_reserved").unwrap().update(tcp.reserved().into());
+            self.pkt_stats.get_mut("tcp_flags_cwr").unwrap().update(tcp.cwr().into());
+            self.pkt_stats.get_mut("tcp_flags_ece").unwrap().update(tcp.ece().into());
+            self.pkt_stats.get_mut("tcp_flags_urg").unwrap().update(tcp.urg().into());
+            self.pkt_stats.get_mut("tcp_flags_ack").unwrap().update(tcp.ack().into());
+            self.pkt_stats.get_mut("tcp_flags_psh").unwrap().update(tcp.psh().into());
+            self.pkt_stats.get_mut("tcp_flags_rst").unwrap().update(tcp.rst().into());
+            self.pkt_stats.get_mut("tcp_flags_syn").unwrap().update(tcp.syn().into());
+            self.pkt_stats.get_mut("tcp_flags_fin").unwrap().update(tcp.fin().into());
+            self.pkt_stats.get_mut("tcp_window_size").unwrap().update(tcp.window().into());
+            self.pkt_stats.get_mut("tcp_checksum").unwrap().update(tcp.checksum().into());
+            self.pkt_stats.get_mut("tcp_urgent_ptr").unwrap().update(tcp.urgent_pointer().into());
+        } else if let Ok(udp) = l3.parse_to_udp() {
+            self.pkt_stats.get_mut("udp_src_port").unwrap().update(udp.src_port().into());
+            self.pkt_stats.get_mut("udp_dst_port").unwrap().update(udp.dst_port().into());
+            self.pkt_stats.get_mut("udp_length").unwrap().update(udp.length().into());
+            self.pkt_stats.get_mut("udp_checksum").unwrap().update(udp.checksum().into());
+        }
+
+        #[cfg(feature = "feature-trace")]
+        trace!(
+            "{} dir={} fields={:?}",
+            five_tuple,
+            dir,
+            Self::capture_trace_snapshot(l3, delta_ns)
+        );
+    }
+
+    /// Captures the same feature values just pushed into `pkt_stats` for one packet, for
+    /// `feature-trace` logging. Not built or called when the feature is disabled.
+    #[cfg(feature = "feature-trace")]
+    fn capture_trace_snapshot(l3: &L3Hdr, delta_ns: u32) -> FragSnapshot {
+        let mut snapshot = FragSnapshot::new();
+        snapshot.insert("packet_iat", delta_ns);
+        snapshot.insert("ip_dscp", l3.dscp());
+        snapshot.insert("ip_ecn", l3.ecn());
+        snapshot.insert("ip_flow_label", l3.flow_label());
+        snapshot.insert("ip_total_length", l3.total_length());
+        snapshot.insert("ip_ttl", l3.ttl());
+        snapshot.insert("ip_protocol", l3.protocol());
+        if let L3Hdr::V4(ipv4) = l3 {
+            snapshot.insert("ip_ihl", ipv4.ihl().into());
+            snapshot.insert("ip_id", ipv4.identification().into());
+            snapshot.insert("ip_flags_rf", ipv4.rf().into());
+            snapshot.insert("ip_flags_df", ipv4.df().into());
+            snapshot.insert("ip_flags_mf", ipv4.mf().into());
+            snapshot.insert("ip_fragment_offset", ipv4.fragment_offset().into());
+            snapshot.insert("ip_header_checksum", ipv4.header_checksum().into());
+        }
+        if let Ok(tcp) = l3.parse_to_tcp() {
+            snapshot.insert("tcp_src_port", tcp.src_port().into());
+            snapshot.insert("tcp_dst_port", tcp.dst_port().into());
+            snapshot.insert("tcp_seq_num", tcp.seq_no().into());
+            snapshot.insert("tcp_ack_num", tcp.ack_no().into());
+            snapshot.insert("tcp_data_offset", tcp.data_offset().into());
+            snapshot.insert("tcp_reserved", tcp.reserved().into());
+            snapshot.insert("tcp_flags_cwr", tcp.cwr().into());
+            snapshot.insert("tcp_flags_ece", tcp.ece().into());
+            snapshot.insert("tcp_flags_urg", tcp.urg().into());
+            snapshot.insert("tcp_flags_ack", tcp.ack().into());
+            snapshot.insert("tcp_flags_psh", tcp.psh().into());
+            snapshot.insert("tcp_flags_rst", tcp.rst().into());
+            snapshot.insert("tcp_flags_syn", tcp.syn().into());
+            snapshot.insert("tcp_flags_fin", tcp.fin().into());
+            snapshot.insert("tcp_window_size", tcp.window().into());
+            snapshot.insert("tcp_checksum", tcp.checksum().into());
+            snapshot.insert("tcp_urgent_ptr", tcp.urgent_pointer().into());
+        } else if let Ok(udp) = l3.parse_to_udp() {
+            snapshot.insert("udp_src_port", udp.src_port().into());
+            snapshot.insert("udp_dst_port", udp.dst_port().into());
+            snapshot.insert("udp_length", udp.length().into());
+            snapshot.insert("udp_checksum", udp.checksum().into());
+        }
+        snapshot
+    }
+
+    /// Buffers one fragment of an IPv4 datagram and, once reassembly completes, records it as a
+    /// single logical packet using the reassembled length and the transport header carried by
+    /// the lead (offset 0) fragment.
+    fn insert_fragment(&mut self, l3: &L3Hdr, ipv4: &Ipv4, curr_tsc: u32, five_tuple: &FiveTuple, dir: bool) {
+        let key = FragKey {
+            src: ipv4.src_addr().into(),
+            dst: ipv4.dst_addr().into(),
+            id: ipv4.identification(),
+            protocol: ipv4.protocol(),
+        };
+        let offset = ipv4.fragment_offset() as u32 * 8;
+        // `total_length` includes the IP header; a malformed packet could claim an `ihl` larger
+        // than its own `total_length`, so this subtracts with saturation rather than wrapping.
+        let length = (ipv4.total_length() as u32).saturating_sub(ipv4.ihl() as u32 * 4);
+        let more_fragments = ipv4.mf();
+        let lead_snapshot = if ipv4.fragment_offset() == 0 {
+            Some(Self::capture_snapshot(l3, ipv4))
+        } else {
+            None
+        };
+        if let Some(snapshot) = self.frag_reassembler.insert(
+            key,
+            offset,
+            length,
+            more_fragments,
+            lead_snapshot,
+            curr_tsc,
+        ) {
+            self.apply_snapshot(&snapshot, curr_tsc, five_tuple, dir);
+        }
+    }
+
+    /// Captures the feature values carried by the lead (offset 0) fragment of an IPv4 datagram,
+    /// to be recorded once reassembly completes. `ip_total_length` is deliberately omitted; the
+    /// reassembled length is filled in by `FragReassembler::insert` once known.
+    fn capture_snapshot(l3: &L3Hdr, ipv4: &Ipv4) -> FragSnapshot {
+        let mut snapshot = FragSnapshot::new();
+        snapshot.insert("ip_dscp", l3.dscp());
+        snapshot.insert("ip_ecn", l3.ecn());
+        snapshot.insert("ip_flow_label", l3.flow_label());
+        snapshot.insert("ip_ttl", l3.ttl());
+        snapshot.insert("ip_protocol", l3.protocol());
+        snapshot.insert("ip_ihl", ipv4.ihl().into());
+        snapshot.insert("ip_id", ipv4.identification().into());
+        snapshot.insert("ip_flags_rf", ipv4.rf().into());
+        snapshot.insert("ip_flags_df", ipv4.df().into());
+        snapshot.insert("ip_flags_mf", ipv4.mf().into());
+        snapshot.insert("ip_fragment_offset", ipv4.fragment_offset().into());
+        snapshot.insert("ip_header_checksum", ipv4.header_checksum().into());
+        if let Ok(tcp) = l3.parse_to_tcp() {
+            snapshot.insert("tcp_src_port", tcp.src_port().into());
+            snapshot.insert("tcp_dst_port", tcp.dst_port().into());
+            snapshot.insert("tcp_seq_num", tcp.seq_no().into());
+            snapshot.insert("tcp_ack_num", tcp.ack_no().into());
+            snapshot.insert("tcp_data_offset", tcp.data_offset().into());
+            snapshot.insert("tcp_reserved", tcp.reserved().into());
+            snapshot.insert("tcp_flags_cwr", tcp.cwr().into());
+            snapshot.insert("tcp_flags_ece", tcp.ece().into());
+            snapshot.insert("tcp_flags_urg", tcp.urg().into());
+            snapshot.insert("tcp_flags_ack", tcp.ack().into());
+            snapshot.insert("tcp_flags_psh", tcp.psh().into());
+            snapshot.insert("tcp_flags_rst", tcp.rst().into());
+            snapshot.insert("tcp_flags_syn", tcp.syn().into());
+            snapshot.insert("tcp_flags_fin", tcp.fin().into());
+            snapshot.insert("tcp_window_size", tcp.window().into());
+            snapshot.insert("tcp_checksum", tcp.checksum().into());
+            snapshot.insert("tcp_urgent_ptr", tcp.urgent_pointer().into());
+        } else if let Ok(udp) = l3.parse_to_udp() {
+            snapshot.insert("udp_src_port", udp.src_port().into());
+            snapshot.insert("udp_dst_port", udp.dst_port().into());
+            snapshot.insert("udp_length", udp.length().into());
+            snapshot.insert("udp_checksum", udp.checksum().into());
+        }
+        snapshot
+    }
+
+    /// Records a reassembled datagram's feature snapshot as a single logical packet.
+    #[cfg_attr(not(feature = "feature-trace"), allow(unused_variables))]
+    fn apply_snapshot(&mut self, snapshot: &FragSnapshot, curr_tsc: u32, five_tuple: &FiveTuple, dir: bool) {
+        let delta_ns = ((curr_tsc - self.start_tsc) as f64 / *TSC_HZ * 1e9) as u32;
+        self.pkt_stats.get_mut("packet_iat").unwrap().update(delta_ns);
+        self.packet_cnt += 1;
+        if let Some(&total_length) = snapshot.get("ip_total_length") {
+            self.byte_cnt += total_length;
+        }
+        for (key, value) in snapshot {
+            if let Some(stats) = self.pkt_stats.get_mut(key) {
+                stats.update(*value);
             }
         }
+
+        #[cfg(feature = "feature-trace")]
+        trace!(
+            "{} dir={} fields={:?} (reassembled)",
+            five_tuple,
+            dir,
+            snapshot
+        );
     }
 }
 
-fn aggregate(agg: &str, raw_features: &[u32]) -> f64 {
-    if raw_features.is_empty() {
-        return 0.0;
-    }
-    let mut data: Data<Vec<f64>> = Data::new(raw_features.iter().map(|&x| x as f64).collect());
-    match agg {
-        "min" => data.min(),
-        "q1" => data.lower_quartile(),
-        "med" => data.median(),
-        "q3" => data.upper_quartile(),
-        "max" => data.max(),
-        "mean" => data.mean().unwrap_or(0.0),
-        "std" => data.std_dev().unwrap_or(0.0),
-        "skew" => data.skewness().unwrap_or(0.0),
-        "kurt" => -1.2,  // need another crate
-        "sum" => raw_features.iter().sum::<u32>() as f64,
-        "dist" => {
-            let unique: HashSet<u32> = raw_features.iter().cloned().collect();
-            unique.len() as f64
-        }
-        "first" => *data.index(0),
-        _ => 0.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `FlowFeatures::new()` and the packet-ingestion path (`insert_segment`/`record_packet`)
+    // require a live `Mbuf`/`L4Pdu`, whose source isn't present in this tree to construct
+    // against in a unit test, so this instead pins down the invariant that actually broke:
+    // every key `record_packet`/`apply_snapshot` look up in `pkt_stats` must be one of the
+    // keys `pkt_stats` was built from. A mismatched literal (e.g. the old `"delta_ns"` vs.
+    // `PACKET_FT_CLASSES`' `"packet_iat"`) would panic on the first packet of every
+    // connection, and a brace-balanced-but-wrong-key typo like that compiles cleanly.
+    #[test]
+    fn pkt_stats_keys_cover_all_ft_classes() {
+        let pkt_stats: HashMap<&str, OnlineAgg> = PACKET_FT_CLASSES
+            .into_iter()
+            .map(|key| (key, OnlineAgg::new()))
+            .collect();
+        for key in [
+            "packet_iat", "ip_dscp", "ip_ecn", "ip_flow_label", "ip_total_length", "ip_ttl",
+            "ip_protocol", "ip_ihl", "ip_id", "ip_flags_rf", "ip_flags_df", "ip_flags_mf",
+            "ip_fragment_offset", "ip_header_checksum", "tcp_src_port", "tcp_dst_port",
+            "tcp_seq_num", "tcp_ack_num", "tcp_data_offset", "tcp_reserved", "tcp_flags_cwr",
+            "tcp_flags_ece", "tcp_flags_urg", "tcp_flags_ack", "tcp_flags_psh", "tcp_flags_rst",
+            "tcp_flags_syn", "tcp_flags_fin", "tcp_window_size", "tcp_checksum", "tcp_urgent_ptr",
+            "udp_src_port", "udp_dst_port", "udp_length", "udp_checksum",
+        ] {
+            assert!(
+                pkt_stats.contains_key(key),
+                "{key} is looked up in record_packet/apply_snapshot but missing from PACKET_FT_CLASSES"
+            );
+        }
+    }
+
+    #[test]
+    fn online_agg_tracks_basic_moments() {
+        let mut agg = OnlineAgg::new();
+        for x in [1u32, 2, 3, 4, 5] {
+            agg.update(x);
+        }
+        assert_eq!(agg.aggregate("min"), 1.0);
+        assert_eq!(agg.aggregate("max"), 5.0);
+        assert_eq!(agg.aggregate("mean"), 3.0);
+        assert_eq!(agg.aggregate("sum"), 15.0);
+        assert_eq!(agg.aggregate("first"), 1.0);
+        assert_eq!(agg.aggregate("dist"), 5.0);
+    }
+
+    #[test]
+    fn online_agg_empty_aggregates_are_zero() {
+        let agg = OnlineAgg::new();
+        assert_eq!(agg.aggregate("mean"), 0.0);
+        assert_eq!(agg.aggregate("min"), 0.0);
+    }
+
+    #[test]
+    fn p2_quantile_median_converges_on_uniform_data() {
+        let mut p2 = P2Quantile::new(0.5);
+        for x in 1..=1001 {
+            p2.update(x as f64);
+        }
+        // True median of 1..=1001 is 501; P^2 is an approximation, so allow slack.
+        assert!((p2.value() - 501.0).abs() < 5.0, "got {}", p2.value());
+    }
+
+    #[test]
+    fn frag_reassembler_total_length_includes_ip_header() {
+        let mut reassembler = FragReassembler::new();
+        let key = FragKey {
+            src: 1,
+            dst: 2,
+            id: 42,
+            protocol: 6,
+        };
+        let mut lead_snapshot = FragSnapshot::new();
+        lead_snapshot.insert("ip_ihl", 5); // 5 * 4 = 20-byte header
+        lead_snapshot.insert("ip_ttl", 64);
+
+        // Lead fragment: offset 0, 100 bytes of payload, more fragments follow.
+        assert!(reassembler
+            .insert(key.clone(), 0, 100, true, Some(lead_snapshot), 0)
+            .is_none());
+
+        // Trailing fragment: offset 100, 50 bytes of payload, reassembly completes here.
+        let snapshot = reassembler
+            .insert(key, 100, 50, false, None, 0)
+            .expect("reassembly should complete once both fragments are in");
+
+        // 100 + 50 bytes of payload plus the 20-byte IP header from the lead fragment.
+        assert_eq!(snapshot.get("ip_total_length"), Some(&170));
     }
 }
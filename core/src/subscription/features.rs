@@ -7,15 +7,17 @@ use crate::dpdk::{rte_get_tsc_hz, rte_rdtsc};
 use crate::filter::FilterResult;
 use crate::memory::mbuf::Mbuf;
 use crate::protocols::packet::ethernet::Ethernet;
-use crate::protocols::packet::ipv4::Ipv4;
-use crate::protocols::packet::tcp::Tcp;
 use crate::protocols::packet::Packet;
 use crate::protocols::stream::{ConnParser, Session, SessionData};
+use crate::subscription::l3::L3Hdr;
+use crate::subscription::quantile::P2Quantile;
 use crate::subscription::*;
 
 use std::fmt;
 
 use anyhow::Result;
+#[cfg(feature = "conn-trace")]
+use log::trace;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 
@@ -74,6 +76,34 @@ pub struct Features {
     s_bytes_med: f64,
     #[cfg(feature = "d_bytes_med")]
     d_bytes_med: f64,
+    #[cfg(feature = "s_bytes_std")]
+    s_bytes_std: f64,
+    #[cfg(feature = "d_bytes_std")]
+    d_bytes_std: f64,
+    #[cfg(feature = "s_winsize_sum")]
+    s_winsize_sum: f64,
+    #[cfg(feature = "d_winsize_sum")]
+    d_winsize_sum: f64,
+    #[cfg(feature = "s_winsize_mean")]
+    s_winsize_mean: f64,
+    #[cfg(feature = "d_winsize_mean")]
+    d_winsize_mean: f64,
+    #[cfg(feature = "s_winsize_min")]
+    s_winsize_min: f64,
+    #[cfg(feature = "d_winsize_min")]
+    d_winsize_min: f64,
+    #[cfg(feature = "s_winsize_max")]
+    s_winsize_max: f64,
+    #[cfg(feature = "d_winsize_max")]
+    d_winsize_max: f64,
+    #[cfg(feature = "s_winsize_med")]
+    s_winsize_med: f64,
+    #[cfg(feature = "d_winsize_med")]
+    d_winsize_med: f64,
+    #[cfg(feature = "s_winsize_std")]
+    s_winsize_std: f64,
+    #[cfg(feature = "d_winsize_std")]
+    d_winsize_std: f64,
 
     #[serde(serialize_with = "serialize_mac_addr")]
     #[cfg(not(feature = "timing"))]
@@ -93,6 +123,214 @@ where
     serializer.serialize_str(&mac.to_string())
 }
 
+/// A minimal, seedable PRNG used to drive fault injection so that a run is reproducible from a
+/// seed alone.
+#[cfg(feature = "fault_injection")]
+struct Xorshift32 {
+    state: u32,
+}
+
+#[cfg(feature = "fault_injection")]
+impl Xorshift32 {
+    fn new(seed: u32) -> Self {
+        Xorshift32 {
+            state: if seed == 0 { 1 } else { seed },
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Returns a pseudo-random `f64` in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / (u32::MAX as f64 + 1.0)
+    }
+}
+
+/// Configuration for the optional packet fault-injection stage, modeled on smoltcp's fault
+/// injector. Lets a user re-run the same pcap with increasing loss and corruption rates to
+/// observe the effect on the emitted features.
+///
+/// Deserializable from a `[fault_injection]` config table, but `load_config` does not read one:
+/// fault injection is active only once `init_fault_injector` has been called with a config.
+#[cfg(feature = "fault_injection")]
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FaultInjectorConfig {
+    /// Probability in `[0.0, 1.0]` that a matched packet is dropped before reaching the conn
+    /// tracker.
+    pub drop_pct: f64,
+    /// Probability in `[0.0, 1.0]` that a matched packet has a single bit flipped.
+    pub corrupt_pct: f64,
+    /// If set, matched packets are truncated to at most this many bytes.
+    pub max_size: Option<usize>,
+    /// If set, matched packets beyond this many per second are dropped.
+    pub max_pkt_rate: Option<u64>,
+    /// Seed for the underlying PRNG.
+    pub seed: u32,
+}
+
+/// Applies drop/corrupt/truncate faults to matched packets ahead of `conn_tracker.process`.
+#[cfg(feature = "fault_injection")]
+pub struct FaultInjector {
+    config: FaultInjectorConfig,
+    rng: Xorshift32,
+    window_start_ns: f64,
+    window_cnt: u64,
+}
+
+#[cfg(feature = "fault_injection")]
+impl FaultInjector {
+    pub fn new(config: FaultInjectorConfig) -> Self {
+        let seed = config.seed;
+        FaultInjector {
+            config,
+            rng: Xorshift32::new(seed),
+            window_start_ns: 0.0,
+            window_cnt: 0,
+        }
+    }
+
+    /// Returns `true` if `mbuf` should be dropped, mutating it in place for truncation and
+    /// corruption faults.
+    fn apply(&mut self, mbuf: &mut Mbuf) -> bool {
+        if let Some(max_rate) = self.config.max_pkt_rate {
+            let now_ns = mbuf.timestamp() as f64 * 1e3;
+            if now_ns - self.window_start_ns >= 1e9 {
+                self.window_start_ns = now_ns;
+                self.window_cnt = 0;
+            }
+            self.window_cnt += 1;
+            if self.window_cnt > max_rate {
+                return true;
+            }
+        }
+        if self.rng.next_f64() < self.config.drop_pct {
+            return true;
+        }
+        if let Some(max_size) = self.config.max_size {
+            mbuf.truncate(max_size);
+        }
+        if self.rng.next_f64() < self.config.corrupt_pct {
+            let data = mbuf.data_mut();
+            if !data.is_empty() {
+                let idx = self.rng.next_u32() as usize % data.len();
+                let bit = 1u8 << (self.rng.next_u32() % 8);
+                data[idx] ^= bit;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(feature = "fault_injection")]
+lazy_static! {
+    static ref FAULT_INJECTOR: std::sync::Mutex<Option<FaultInjector>> =
+        std::sync::Mutex::new(None);
+}
+
+/// Installs the process-wide fault injector built from `config`. No-op if never called,
+/// which leaves fault injection disabled.
+#[cfg(feature = "fault_injection")]
+pub fn init_fault_injector(config: FaultInjectorConfig) {
+    *FAULT_INJECTOR.lock().unwrap() = Some(FaultInjector::new(config));
+}
+
+/// The clock used to timestamp packets for `dur`/`*_iat_mean`/handshake-timing features.
+///
+/// `Hardware` reads the NIC RX timestamp already carried on the mbuf (`Mbuf::timestamp`), which
+/// reflects when the packet actually arrived on the wire. `Software` instead takes a single
+/// `rte_rdtsc()` reading at packet-processing time, for devices without RX timestamp offload;
+/// under backlog this measures processing delay rather than true inter-arrival time, so it's a
+/// fallback rather than the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum ClockSource {
+    Hardware,
+    Software,
+}
+
+lazy_static! {
+    static ref CLOCK_SOURCE: std::sync::Mutex<ClockSource> =
+        std::sync::Mutex::new(ClockSource::Hardware);
+}
+
+/// Selects the process-wide clock source. `load_config` does not set it; if this is never
+/// called, the hardware RX timestamp stays in effect.
+pub fn init_clock_source(source: ClockSource) {
+    *CLOCK_SOURCE.lock().unwrap() = source;
+}
+
+/// Returns the arrival timestamp of `segment`, in nanoseconds, using the configured clock
+/// source. Replaces the one-off `rte_rdtsc()` reads that used to be sprinkled through `update`.
+fn capture_ts(segment: &L4Pdu) -> f64 {
+    match *CLOCK_SOURCE.lock().unwrap() {
+        ClockSource::Hardware => segment.mbuf_ref().timestamp() as f64 * 1e3,
+        ClockSource::Software => unsafe { rte_rdtsc() } as f64 / *TSC_GHZ,
+    }
+}
+
+/// A per-connection PCAP sidecar, modeled on smoltcp's `pcap_writer`: a global header followed
+/// by one record per packet, so the raw trace behind a feature record can be inspected or
+/// re-labeled offline. The filename embeds the connection's five-tuple so each JSONL line can be
+/// joined back to its packet trace.
+#[cfg(feature = "pcap_sink")]
+struct PcapSink {
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+#[cfg(feature = "pcap_sink")]
+impl PcapSink {
+    const MAGIC: u32 = 0xa1b2c3d4;
+    const LINKTYPE_ETHERNET: u32 = 1;
+
+    fn new(five_tuple: &FiveTuple) -> std::io::Result<Self> {
+        use std::io::Write;
+
+        let name: String = format!("{}", five_tuple)
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let file = std::fs::File::create(format!("{}.pcap", name))?;
+        let mut file = std::io::BufWriter::new(file);
+        file.write_all(&Self::MAGIC.to_ne_bytes())?;
+        file.write_all(&2u16.to_ne_bytes())?;
+        file.write_all(&4u16.to_ne_bytes())?;
+        file.write_all(&0i32.to_ne_bytes())?;
+        file.write_all(&0u32.to_ne_bytes())?;
+        file.write_all(&65535u32.to_ne_bytes())?;
+        file.write_all(&Self::LINKTYPE_ETHERNET.to_ne_bytes())?;
+        Ok(PcapSink { file })
+    }
+
+    /// Appends one per-packet record: `ts_sec, ts_usec, incl_len, orig_len` followed by the raw
+    /// bytes of `mbuf`.
+    fn write_packet(&mut self, mbuf: &Mbuf) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let ts_us = mbuf.timestamp();
+        let ts_sec = (ts_us / 1_000_000) as u32;
+        let ts_usec = (ts_us % 1_000_000) as u32;
+        let data = mbuf.data();
+        let len = data.len() as u32;
+        self.file.write_all(&ts_sec.to_ne_bytes())?;
+        self.file.write_all(&ts_usec.to_ne_bytes())?;
+        self.file.write_all(&len.to_ne_bytes())?;
+        self.file.write_all(&len.to_ne_bytes())?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        use std::io::Write;
+        self.file.flush()
+    }
+}
+
 impl Subscribable for Features {
     type Tracked = TrackedFeatures;
 
@@ -105,12 +343,19 @@ impl Subscribable for Features {
     }
 
     fn process_packet(
-        mbuf: Mbuf,
+        #[allow(unused_mut)] mut mbuf: Mbuf,
         subscription: &Subscription<Self>,
         conn_tracker: &mut ConnTracker<Self::Tracked>,
     ) {
         match subscription.filter_packet(&mbuf) {
             FilterResult::MatchTerminal(idx) | FilterResult::MatchNonTerminal(idx) => {
+                #[cfg(feature = "fault_injection")]
+                if let Some(injector) = FAULT_INJECTOR.lock().unwrap().as_mut() {
+                    if injector.apply(&mut mbuf) {
+                        drop(mbuf);
+                        return;
+                    }
+                }
                 if let Ok(ctxt) = L4Context::new(&mbuf, idx) {
                     conn_tracker.process(mbuf, ctxt, subscription);
                 }
@@ -168,13 +413,19 @@ pub struct TrackedFeatures {
         feature = "s_pkt_cnt",
         feature = "s_bytes_mean",
         feature = "s_iat_mean",
+        feature = "s_bytes_std",
+        feature = "s_winsize_mean",
+        feature = "s_winsize_std",
     ))]
     s_pkt_cnt: f64,
     #[cfg(any(
         feature = "d_ttl_mean",
         feature = "d_pkt_cnt",
         feature = "d_bytes_mean",
-        feature = "d_iat_mean"
+        feature = "d_iat_mean",
+        feature = "d_bytes_std",
+        feature = "d_winsize_mean",
+        feature = "d_winsize_std",
     ))]
     d_pkt_cnt: f64,
     #[cfg(any(feature = "s_bytes_sum", feature = "s_load", feature = "s_bytes_mean"))]
@@ -196,13 +447,54 @@ pub struct TrackedFeatures {
     #[cfg(feature = "d_bytes_max")]
     d_bytes_max: f64,
     #[cfg(feature = "s_bytes_med")]
-    s_bytes_hist: Vec<f64>,
+    s_bytes_med: P2Quantile,
     #[cfg(feature = "d_bytes_med")]
-    d_bytes_hist: Vec<f64>,
+    d_bytes_med: P2Quantile,
+    #[cfg(feature = "s_bytes_std")]
+    s_bytes_mean_w: f64,
+    #[cfg(feature = "s_bytes_std")]
+    s_bytes_m2: f64,
+    #[cfg(feature = "d_bytes_std")]
+    d_bytes_mean_w: f64,
+    #[cfg(feature = "d_bytes_std")]
+    d_bytes_m2: f64,
+    #[cfg(any(feature = "s_winsize_sum", feature = "s_winsize_mean"))]
+    s_winsize_sum: f64,
+    #[cfg(any(feature = "d_winsize_sum", feature = "d_winsize_mean"))]
+    d_winsize_sum: f64,
+    #[cfg(feature = "s_winsize_min")]
+    s_winsize_min: f64,
+    #[cfg(feature = "d_winsize_min")]
+    d_winsize_min: f64,
+    #[cfg(feature = "s_winsize_max")]
+    s_winsize_max: f64,
+    #[cfg(feature = "d_winsize_max")]
+    d_winsize_max: f64,
+    #[cfg(feature = "s_winsize_med")]
+    s_winsize_med: P2Quantile,
+    #[cfg(feature = "d_winsize_med")]
+    d_winsize_med: P2Quantile,
+    #[cfg(feature = "s_winsize_std")]
+    s_winsize_mean_w: f64,
+    #[cfg(feature = "s_winsize_std")]
+    s_winsize_m2: f64,
+    #[cfg(feature = "d_winsize_std")]
+    d_winsize_mean_w: f64,
+    #[cfg(feature = "d_winsize_std")]
+    d_winsize_m2: f64,
     #[cfg(not(feature = "timing"))]
     s_mac: pnet::datalink::MacAddr,
     #[cfg(not(feature = "timing"))]
     d_mac: pnet::datalink::MacAddr,
+    #[cfg(feature = "pcap_sink")]
+    pcap_sink: Option<PcapSink>,
+    /// Five-tuple of this connection, retained only to identify it in `conn-trace` log lines.
+    #[cfg(feature = "conn-trace")]
+    five_tuple: FiveTuple,
+    /// Timestamp of the first segment, so `conn-trace` can log timestamps relative to the start
+    /// of the connection instead of the raw clock reading.
+    #[cfg(feature = "conn-trace")]
+    conn_start_ts: f64,
 }
 
 impl TrackedFeatures {
@@ -212,6 +504,43 @@ impl TrackedFeatures {
         #[cfg(feature = "timing")]
         let start_ts = (unsafe { rte_rdtsc() } as f64 / *TSC_GHZ) as u64;
 
+        #[cfg(feature = "conn-trace")]
+        {
+            let conn_trace_ts = capture_ts(&segment);
+            if self.cnt == 1 {
+                self.conn_start_ts = conn_trace_ts;
+            }
+            let mbuf = segment.mbuf_ref();
+            if let Ok(eth) = mbuf.parse_to::<Ethernet>() {
+                if let Ok(l3) = L3Hdr::parse(eth) {
+                    if let Ok(tcp) = l3.parse_to_tcp() {
+                        let transition = if tcp.syn() && tcp.ack() {
+                            "SYN-ACK"
+                        } else if tcp.syn() {
+                            "SYN"
+                        } else if tcp.ack() {
+                            "ACK"
+                        } else {
+                            "DATA"
+                        };
+                        trace!(
+                            "{} dir={} ts={:.0} ip_len={} tcp_syn={} tcp_ack={} tcp_fin={} tcp_rst={} tcp_win={} transition={}",
+                            self.five_tuple,
+                            segment.dir,
+                            conn_trace_ts - self.conn_start_ts,
+                            l3.total_length() as f64,
+                            tcp.syn(),
+                            tcp.ack(),
+                            tcp.fin(),
+                            tcp.rst(),
+                            tcp.window(),
+                            transition
+                        );
+                    }
+                }
+            }
+        }
+
         #[cfg(any(
             feature = "dur",
             feature = "s_load",
@@ -222,9 +551,7 @@ impl TrackedFeatures {
             feature = "syn_ack",
             feature = "ack_dat",
         ))]
-        let curr_ts = unsafe { rte_rdtsc() } as f64 / *TSC_GHZ;
-        #[cfg(not(feature = "timing"))]
-        let curr_ts = segment.mbuf_ref().timestamp() as f64 * 1e3;
+        let curr_ts = capture_ts(&segment);
 
         #[cfg(any(
             feature = "proto",
@@ -246,6 +573,20 @@ impl TrackedFeatures {
             feature = "d_bytes_max",
             feature = "s_bytes_med",
             feature = "d_bytes_med",
+            feature = "s_bytes_std",
+            feature = "d_bytes_std",
+            feature = "s_winsize_sum",
+            feature = "d_winsize_sum",
+            feature = "s_winsize_mean",
+            feature = "d_winsize_mean",
+            feature = "s_winsize_min",
+            feature = "d_winsize_min",
+            feature = "s_winsize_max",
+            feature = "d_winsize_max",
+            feature = "s_winsize_med",
+            feature = "d_winsize_med",
+            feature = "s_winsize_std",
+            feature = "d_winsize_std",
         ))]
         let mbuf = segment.mbuf_ref();
         #[cfg(any(
@@ -268,6 +609,20 @@ impl TrackedFeatures {
             feature = "d_bytes_max",
             feature = "s_bytes_med",
             feature = "d_bytes_med",
+            feature = "s_bytes_std",
+            feature = "d_bytes_std",
+            feature = "s_winsize_sum",
+            feature = "d_winsize_sum",
+            feature = "s_winsize_mean",
+            feature = "d_winsize_mean",
+            feature = "s_winsize_min",
+            feature = "d_winsize_min",
+            feature = "s_winsize_max",
+            feature = "d_winsize_max",
+            feature = "s_winsize_med",
+            feature = "d_winsize_med",
+            feature = "s_winsize_std",
+            feature = "d_winsize_std",
         ))]
         let eth = mbuf.parse_to::<Ethernet>()?;
         #[cfg(any(
@@ -290,8 +645,37 @@ impl TrackedFeatures {
             feature = "d_bytes_max",
             feature = "s_bytes_med",
             feature = "d_bytes_med",
+            feature = "s_bytes_std",
+            feature = "d_bytes_std",
+            feature = "s_winsize_sum",
+            feature = "d_winsize_sum",
+            feature = "s_winsize_mean",
+            feature = "d_winsize_mean",
+            feature = "s_winsize_min",
+            feature = "d_winsize_min",
+            feature = "s_winsize_max",
+            feature = "d_winsize_max",
+            feature = "s_winsize_med",
+            feature = "d_winsize_med",
+            feature = "s_winsize_std",
+            feature = "d_winsize_std",
+        ))]
+        let l3 = L3Hdr::parse(eth)?;
+        #[cfg(any(
+            feature = "s_winsize_sum",
+            feature = "d_winsize_sum",
+            feature = "s_winsize_mean",
+            feature = "d_winsize_mean",
+            feature = "s_winsize_min",
+            feature = "d_winsize_min",
+            feature = "s_winsize_max",
+            feature = "d_winsize_max",
+            feature = "s_winsize_med",
+            feature = "d_winsize_med",
+            feature = "s_winsize_std",
+            feature = "d_winsize_std",
         ))]
-        let ipv4 = eth.parse_to::<Ipv4>()?;
+        let tcp = l3.parse_to_tcp()?;
 
         if segment.dir {
             #[cfg(not(feature = "timing"))]
@@ -328,40 +712,73 @@ impl TrackedFeatures {
                 feature = "s_pkt_cnt",
                 feature = "s_bytes_mean",
                 feature = "s_iat_mean",
+                feature = "s_bytes_std",
+                feature = "s_winsize_mean",
+                feature = "s_winsize_std",
             ))]
             {
                 self.s_pkt_cnt += 1.0;
             }
+            #[cfg(feature = "s_bytes_std")]
+            {
+                let delta = l3.total_length() as f64 - self.s_bytes_mean_w;
+                self.s_bytes_mean_w += delta / self.s_pkt_cnt;
+                let delta2 = l3.total_length() as f64 - self.s_bytes_mean_w;
+                self.s_bytes_m2 += delta * delta2;
+            }
             #[cfg(any(feature = "s_bytes_sum", feature = "s_load", feature = "s_bytes_mean"))]
             {
-                self.s_bytes_sum += ipv4.total_length() as f64;
+                self.s_bytes_sum += l3.total_length() as f64;
             }
             #[cfg(feature = "s_bytes_min")]
             {
-                self.s_bytes_min = self.s_bytes_min.min(ipv4.total_length() as f64);
+                self.s_bytes_min = self.s_bytes_min.min(l3.total_length() as f64);
             }
             #[cfg(feature = "s_bytes_max")]
             {
-                self.s_bytes_max = self.s_bytes_max.max(ipv4.total_length() as f64);
+                self.s_bytes_max = self.s_bytes_max.max(l3.total_length() as f64);
             }
             #[cfg(any(feature = "s_bytes_med"))]
             {
-                self.s_bytes_hist.push(ipv4.total_length() as f64);
+                self.s_bytes_med.update(l3.total_length() as f64);
+            }
+            #[cfg(feature = "s_winsize_std")]
+            {
+                let delta = tcp.window() as f64 - self.s_winsize_mean_w;
+                self.s_winsize_mean_w += delta / self.s_pkt_cnt;
+                let delta2 = tcp.window() as f64 - self.s_winsize_mean_w;
+                self.s_winsize_m2 += delta * delta2;
+            }
+            #[cfg(any(feature = "s_winsize_sum", feature = "s_winsize_mean"))]
+            {
+                self.s_winsize_sum += tcp.window() as f64;
+            }
+            #[cfg(feature = "s_winsize_min")]
+            {
+                self.s_winsize_min = self.s_winsize_min.min(tcp.window() as f64);
+            }
+            #[cfg(feature = "s_winsize_max")]
+            {
+                self.s_winsize_max = self.s_winsize_max.max(tcp.window() as f64);
+            }
+            #[cfg(any(feature = "s_winsize_med"))]
+            {
+                self.s_winsize_med.update(tcp.window() as f64);
             }
             #[cfg(feature = "s_ttl_mean")]
             {
-                self.s_ttl_sum += ipv4.time_to_live() as f64;
+                self.s_ttl_sum += l3.ttl() as f64;
             }
             #[cfg(any(feature = "tcp_rtt", feature = "ack_dat",))]
             if !self.syn_ack_ts.is_nan() && self.ack_ts.is_nan() {
-                let tcp = ipv4.parse_to::<Tcp>()?;
+                let tcp = l3.parse_to_tcp()?;
                 if tcp.ack() {
                     self.ack_ts = curr_ts;
                 }
             }
             #[cfg(feature = "proto")]
             {
-                self.proto = ipv4.protocol() as f64;
+                self.proto = l3.protocol() as f64;
             }
         } else {
             #[cfg(any(
@@ -377,30 +794,63 @@ impl TrackedFeatures {
                 feature = "d_ttl_mean",
                 feature = "d_pkt_cnt",
                 feature = "d_bytes_mean",
-                feature = "d_iat_mean"
+                feature = "d_iat_mean",
+                feature = "d_bytes_std",
+                feature = "d_winsize_mean",
+                feature = "d_winsize_std",
             ))]
             {
                 self.d_pkt_cnt += 1.0;
             }
+            #[cfg(feature = "d_bytes_std")]
+            {
+                let delta = l3.total_length() as f64 - self.d_bytes_mean_w;
+                self.d_bytes_mean_w += delta / self.d_pkt_cnt;
+                let delta2 = l3.total_length() as f64 - self.d_bytes_mean_w;
+                self.d_bytes_m2 += delta * delta2;
+            }
             #[cfg(any(feature = "d_bytes_sum", feature = "d_load", feature = "d_bytes_mean"))]
             {
-                self.d_bytes_sum += ipv4.total_length() as f64;
+                self.d_bytes_sum += l3.total_length() as f64;
             }
             #[cfg(feature = "d_bytes_min")]
             {
-                self.d_bytes_min = self.d_bytes_min.min(ipv4.total_length() as f64);
+                self.d_bytes_min = self.d_bytes_min.min(l3.total_length() as f64);
             }
             #[cfg(feature = "d_bytes_max")]
             {
-                self.d_bytes_max = self.d_bytes_max.max(ipv4.total_length() as f64);
+                self.d_bytes_max = self.d_bytes_max.max(l3.total_length() as f64);
             }
             #[cfg(any(feature = "d_bytes_med"))]
             {
-                self.d_bytes_hist.push(ipv4.total_length() as f64);
+                self.d_bytes_med.update(l3.total_length() as f64);
+            }
+            #[cfg(feature = "d_winsize_std")]
+            {
+                let delta = tcp.window() as f64 - self.d_winsize_mean_w;
+                self.d_winsize_mean_w += delta / self.d_pkt_cnt;
+                let delta2 = tcp.window() as f64 - self.d_winsize_mean_w;
+                self.d_winsize_m2 += delta * delta2;
+            }
+            #[cfg(any(feature = "d_winsize_sum", feature = "d_winsize_mean"))]
+            {
+                self.d_winsize_sum += tcp.window() as f64;
+            }
+            #[cfg(feature = "d_winsize_min")]
+            {
+                self.d_winsize_min = self.d_winsize_min.min(tcp.window() as f64);
+            }
+            #[cfg(feature = "d_winsize_max")]
+            {
+                self.d_winsize_max = self.d_winsize_max.max(tcp.window() as f64);
+            }
+            #[cfg(any(feature = "d_winsize_med"))]
+            {
+                self.d_winsize_med.update(tcp.window() as f64);
             }
             #[cfg(any(feature = "d_ttl_mean"))]
             {
-                self.d_ttl_sum += ipv4.time_to_live() as f64;
+                self.d_ttl_sum += l3.ttl() as f64;
             }
             #[cfg(any(
                 feature = "d_iat_mean",
@@ -409,7 +859,7 @@ impl TrackedFeatures {
                 feature = "ack_dat",
             ))]
             if self.syn_ack_ts.is_nan() {
-                let tcp = ipv4.parse_to::<Tcp>()?;
+                let tcp = l3.parse_to_tcp()?;
                 if tcp.synack() {
                     self.syn_ack_ts = curr_ts;
                 }
@@ -453,9 +903,41 @@ impl TrackedFeatures {
         #[cfg(feature = "tcp_rtt")]
         let tcp_rtt = syn_ack + ack_dat;
         #[cfg(any(feature = "s_bytes_med"))]
-        let s_bytes_med = median(&mut self.s_bytes_hist);
+        let s_bytes_med = self.s_bytes_med.value();
         #[cfg(any(feature = "d_bytes_med"))]
-        let d_bytes_med = median(&mut self.d_bytes_hist);
+        let d_bytes_med = self.d_bytes_med.value();
+        #[cfg(feature = "s_bytes_std")]
+        let s_bytes_std = if self.s_pkt_cnt < 2.0 {
+            f64::NAN
+        } else {
+            (self.s_bytes_m2 / (self.s_pkt_cnt - 1.0)).sqrt()
+        };
+        #[cfg(feature = "d_bytes_std")]
+        let d_bytes_std = if self.d_pkt_cnt < 2.0 {
+            f64::NAN
+        } else {
+            (self.d_bytes_m2 / (self.d_pkt_cnt - 1.0)).sqrt()
+        };
+        #[cfg(any(feature = "s_winsize_mean"))]
+        let s_winsize_mean = self.s_winsize_sum / self.s_pkt_cnt;
+        #[cfg(any(feature = "d_winsize_mean"))]
+        let d_winsize_mean = self.d_winsize_sum / self.d_pkt_cnt;
+        #[cfg(any(feature = "s_winsize_med"))]
+        let s_winsize_med = self.s_winsize_med.value();
+        #[cfg(any(feature = "d_winsize_med"))]
+        let d_winsize_med = self.d_winsize_med.value();
+        #[cfg(feature = "s_winsize_std")]
+        let s_winsize_std = if self.s_pkt_cnt < 2.0 {
+            f64::NAN
+        } else {
+            (self.s_winsize_m2 / (self.s_pkt_cnt - 1.0)).sqrt()
+        };
+        #[cfg(feature = "d_winsize_std")]
+        let d_winsize_std = if self.d_pkt_cnt < 2.0 {
+            f64::NAN
+        } else {
+            (self.d_winsize_m2 / (self.d_pkt_cnt - 1.0)).sqrt()
+        };
         let features = Features {
             #[cfg(feature = "dur")]
             dur,
@@ -503,6 +985,34 @@ impl TrackedFeatures {
             s_bytes_med,
             #[cfg(feature = "d_bytes_med")]
             d_bytes_med,
+            #[cfg(feature = "s_bytes_std")]
+            s_bytes_std,
+            #[cfg(feature = "d_bytes_std")]
+            d_bytes_std,
+            #[cfg(feature = "s_winsize_sum")]
+            s_winsize_sum: self.s_winsize_sum,
+            #[cfg(feature = "d_winsize_sum")]
+            d_winsize_sum: self.d_winsize_sum,
+            #[cfg(feature = "s_winsize_mean")]
+            s_winsize_mean,
+            #[cfg(feature = "d_winsize_mean")]
+            d_winsize_mean,
+            #[cfg(feature = "s_winsize_min")]
+            s_winsize_min: self.s_winsize_min,
+            #[cfg(feature = "d_winsize_min")]
+            d_winsize_min: self.d_winsize_min,
+            #[cfg(feature = "s_winsize_max")]
+            s_winsize_max: self.s_winsize_max,
+            #[cfg(feature = "d_winsize_max")]
+            d_winsize_max: self.d_winsize_max,
+            #[cfg(feature = "s_winsize_med")]
+            s_winsize_med,
+            #[cfg(feature = "d_winsize_med")]
+            d_winsize_med,
+            #[cfg(feature = "s_winsize_std")]
+            s_winsize_std,
+            #[cfg(feature = "d_winsize_std")]
+            d_winsize_std,
 
             #[cfg(not(feature = "timing"))]
             s_mac: self.s_mac,
@@ -522,7 +1032,8 @@ impl TrackedFeatures {
 impl Trackable for TrackedFeatures {
     type Subscribed = Features;
 
-    fn new(_five_tuple: FiveTuple) -> Self {
+    #[cfg_attr(not(feature = "pcap_sink"), allow(unused_variables))]
+    fn new(five_tuple: FiveTuple) -> Self {
         TrackedFeatures {
             #[cfg(feature = "timing")]
             compute_ns: 0,
@@ -565,6 +1076,9 @@ impl Trackable for TrackedFeatures {
                 feature = "s_pkt_cnt",
                 feature = "s_bytes_mean",
                 feature = "s_iat_mean",
+                feature = "s_bytes_std",
+                feature = "s_winsize_mean",
+                feature = "s_winsize_std",
             ))]
             s_pkt_cnt: 0.0,
             #[cfg(any(
@@ -572,6 +1086,9 @@ impl Trackable for TrackedFeatures {
                 feature = "d_pkt_cnt",
                 feature = "d_bytes_mean",
                 feature = "d_iat_mean",
+                feature = "d_bytes_std",
+                feature = "d_winsize_mean",
+                feature = "d_winsize_std",
             ))]
             d_pkt_cnt: 0.0,
             #[cfg(any(feature = "s_bytes_sum", feature = "s_load", feature = "s_bytes_mean"))]
@@ -593,14 +1110,52 @@ impl Trackable for TrackedFeatures {
             #[cfg(feature = "d_bytes_max")]
             d_bytes_max: f64::NAN,
             #[cfg(any(feature = "s_bytes_med"))]
-            s_bytes_hist: vec![],
+            s_bytes_med: P2Quantile::new(0.5),
             #[cfg(any(feature = "d_bytes_med"))]
-            d_bytes_hist: vec![],
+            d_bytes_med: P2Quantile::new(0.5),
+            #[cfg(feature = "s_bytes_std")]
+            s_bytes_mean_w: 0.0,
+            #[cfg(feature = "s_bytes_std")]
+            s_bytes_m2: 0.0,
+            #[cfg(feature = "d_bytes_std")]
+            d_bytes_mean_w: 0.0,
+            #[cfg(feature = "d_bytes_std")]
+            d_bytes_m2: 0.0,
+            #[cfg(any(feature = "s_winsize_sum", feature = "s_winsize_mean"))]
+            s_winsize_sum: 0.0,
+            #[cfg(any(feature = "d_winsize_sum", feature = "d_winsize_mean"))]
+            d_winsize_sum: 0.0,
+            #[cfg(feature = "s_winsize_min")]
+            s_winsize_min: f64::NAN,
+            #[cfg(feature = "d_winsize_min")]
+            d_winsize_min: f64::NAN,
+            #[cfg(feature = "s_winsize_max")]
+            s_winsize_max: f64::NAN,
+            #[cfg(feature = "d_winsize_max")]
+            d_winsize_max: f64::NAN,
+            #[cfg(feature = "s_winsize_med")]
+            s_winsize_med: P2Quantile::new(0.5),
+            #[cfg(feature = "d_winsize_med")]
+            d_winsize_med: P2Quantile::new(0.5),
+            #[cfg(feature = "s_winsize_std")]
+            s_winsize_mean_w: 0.0,
+            #[cfg(feature = "s_winsize_std")]
+            s_winsize_m2: 0.0,
+            #[cfg(feature = "d_winsize_std")]
+            d_winsize_mean_w: 0.0,
+            #[cfg(feature = "d_winsize_std")]
+            d_winsize_m2: 0.0,
 
             #[cfg(not(feature = "timing"))]
             s_mac: pnet::datalink::MacAddr::zero(),
             #[cfg(not(feature = "timing"))]
             d_mac: pnet::datalink::MacAddr::zero(),
+            #[cfg(feature = "pcap_sink")]
+            pcap_sink: PcapSink::new(&five_tuple).ok(),
+            #[cfg(feature = "conn-trace")]
+            five_tuple,
+            #[cfg(feature = "conn-trace")]
+            conn_start_ts: f64::NAN,
         }
     }
 
@@ -610,6 +1165,10 @@ impl Trackable for TrackedFeatures {
         _session_id: Option<usize>,
         subscription: &Subscription<Self::Subscribed>,
     ) {
+        #[cfg(feature = "pcap_sink")]
+        if let Some(sink) = self.pcap_sink.as_mut() {
+            let _ = sink.write_packet(pdu.mbuf_ref());
+        }
         timer_start!(t);
         self.update(pdu).unwrap_or(());
         timer_elapsed_nanos!(subscription.timers, "update", t);
@@ -622,6 +1181,10 @@ impl Trackable for TrackedFeatures {
     }
 
     fn post_match(&mut self, pdu: L4Pdu, subscription: &Subscription<Self::Subscribed>) {
+        #[cfg(feature = "pcap_sink")]
+        if let Some(sink) = self.pcap_sink.as_mut() {
+            let _ = sink.write_packet(pdu.mbuf_ref());
+        }
         timer_start!(t);
         self.update(pdu).unwrap_or(());
         timer_elapsed_nanos!(subscription.timers, "update", t);
@@ -632,6 +1195,11 @@ impl Trackable for TrackedFeatures {
         let features = self.extract_features();
         timer_elapsed_nanos!(subscription.timers, "extract_features", t);
 
+        #[cfg(feature = "pcap_sink")]
+        if let Some(sink) = self.pcap_sink.as_mut() {
+            let _ = sink.flush();
+        }
+
         let conn = features;
         timer_record!(subscription.timers, "compute_ns", self.compute_ns);
         subscription.invoke(conn);
@@ -641,16 +1209,3 @@ impl Trackable for TrackedFeatures {
         false
     }
 }
-
-fn median(numbers: &mut [f64]) -> f64 {
-    if numbers.is_empty() {
-        return f64::NAN;
-    }
-    numbers.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let mid = numbers.len() / 2;
-    if numbers.len() % 2 == 1 {
-        numbers[mid]
-    } else {
-        (numbers[mid-1] + numbers[mid]) / 2.0
-    }
-}
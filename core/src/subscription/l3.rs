@@ -0,0 +1,91 @@
+//! Shared IPv4/IPv6 dispatch for packet-level feature extraction.
+
+use crate::protocols::packet::ethernet::Ethernet;
+use crate::protocols::packet::ipv4::Ipv4;
+use crate::protocols::packet::ipv6::Ipv6;
+use crate::protocols::packet::tcp::Tcp;
+use crate::protocols::packet::udp::Udp;
+use crate::protocols::packet::Packet;
+
+use anyhow::{bail, Result};
+use pnet::packet::ethernet::EtherTypes;
+
+/// The network-layer header of a packet, dispatched on EtherType so callers can treat IPv4 and
+/// IPv6 uniformly. Fields with no IPv6 analog (header length, identification, fragmentation
+/// flags, header checksum) are only meaningful for IPv4; mixed-stack captures simply leave those
+/// columns empty for IPv6 flows.
+pub(crate) enum L3Hdr<'a> {
+    V4(Ipv4<'a>),
+    V6(Ipv6<'a>),
+}
+
+impl<'a> L3Hdr<'a> {
+    pub(crate) fn parse(eth: Ethernet<'a>) -> Result<Self> {
+        match eth.ethertype() {
+            EtherTypes::Ipv4 => Ok(L3Hdr::V4(eth.parse_to::<Ipv4>()?)),
+            EtherTypes::Ipv6 => Ok(L3Hdr::V6(eth.parse_to::<Ipv6>()?)),
+            _ => bail!("Unsupported EtherType"),
+        }
+    }
+
+    /// Total datagram length in bytes, i.e. IPv4 `total_length` or the IPv6 analog
+    /// `payload_length() + 40` (the fixed IPv6 header length).
+    pub(crate) fn total_length(&self) -> u32 {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.total_length().into(),
+            L3Hdr::V6(ipv6) => ipv6.payload_length() as u32 + 40,
+        }
+    }
+
+    /// Hop count, i.e. IPv4 `time_to_live` or the IPv6 analog `hop_limit`.
+    pub(crate) fn ttl(&self) -> u32 {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.time_to_live().into(),
+            L3Hdr::V6(ipv6) => ipv6.hop_limit().into(),
+        }
+    }
+
+    /// Upper-layer protocol, i.e. IPv4 `protocol` or the IPv6 analog `next_header`.
+    pub(crate) fn protocol(&self) -> u32 {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.protocol().into(),
+            L3Hdr::V6(ipv6) => ipv6.next_header().into(),
+        }
+    }
+
+    pub(crate) fn dscp(&self) -> u32 {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.dscp().into(),
+            L3Hdr::V6(ipv6) => ipv6.dscp().into(),
+        }
+    }
+
+    pub(crate) fn ecn(&self) -> u32 {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.ecn().into(),
+            L3Hdr::V6(ipv6) => ipv6.ecn().into(),
+        }
+    }
+
+    /// IPv6 flow label, or 0 for IPv4 (which has no equivalent field).
+    pub(crate) fn flow_label(&self) -> u32 {
+        match self {
+            L3Hdr::V4(_) => 0,
+            L3Hdr::V6(ipv6) => ipv6.flow_label(),
+        }
+    }
+
+    pub(crate) fn parse_to_tcp(&self) -> Result<Tcp<'_>> {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.parse_to::<Tcp>(),
+            L3Hdr::V6(ipv6) => ipv6.parse_to::<Tcp>(),
+        }
+    }
+
+    pub(crate) fn parse_to_udp(&self) -> Result<Udp<'_>> {
+        match self {
+            L3Hdr::V4(ipv4) => ipv4.parse_to::<Udp>(),
+            L3Hdr::V6(ipv6) => ipv6.parse_to::<Udp>(),
+        }
+    }
+}
@@ -21,17 +21,22 @@ use crate::filter::FilterResult;
 use crate::memory::mbuf::Mbuf;
 use crate::protocols::packet::ethernet::Ethernet;
 use crate::protocols::packet::ipv4::Ipv4;
+use crate::protocols::packet::ipv6::Ipv6;
 use crate::protocols::packet::Packet;
 use crate::protocols::stream::{ConnParser, Session};
 use crate::subscription::{Level, Subscribable, Subscription, Trackable};
 
 use std::fmt;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::Utc;
+use pnet::packet::ethernet::EtherTypes;
 use serde::ser::{SerializeSeq, Serializer};
 use serde::Serialize;
 
+#[cfg(feature = "feature-trace")]
+use log::trace;
+
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -120,7 +125,8 @@ pub struct TrackedPacketFeatures {
 impl TrackedPacketFeatures {
     #[inline]
     fn update(&mut self, segment: L4Pdu) {
-        if let Ok(packet) = PacketFeature::from(segment, self.start_tsc) {
+        let dir = segment.dir;
+        if let Ok(packet) = PacketFeature::from(segment, self.start_tsc, &self.five_tuple, dir) {
             self.packets.push(packet);
         }
     }
@@ -172,7 +178,7 @@ pub struct PacketFeature {
     pub dir: u32,
     /// time offset from start of connection in ns
     pub offset: u32,
-    /// size of IP packet (IPv4 total length)
+    /// size of IP packet (IPv4 total length, or IPv6 payload length + header)
     pub sz: u32,
 }
 
@@ -190,17 +196,32 @@ impl Serialize for PacketFeature {
 }
 
 impl PacketFeature {
-    fn from(segment: L4Pdu, start_tsc: u64) -> Result<Self> {
+    #[cfg_attr(not(feature = "feature-trace"), allow(unused_variables))]
+    fn from(segment: L4Pdu, start_tsc: u64, five_tuple: &FiveTuple, dir: bool) -> Result<Self> {
         let curr_tsc = unsafe { rte_rdtsc() };
         let delta_ns = ((curr_tsc.saturating_sub(start_tsc)) as f64 / *TSC_HZ * 1e9) as u32;
         let mbuf: &Mbuf = segment.mbuf_ref();
         let eth = mbuf.parse_to::<Ethernet>()?;
-        let ipv4 = eth.parse_to::<Ipv4>()?;
+        let sz = match eth.ethertype() {
+            EtherTypes::Ipv4 => eth.parse_to::<Ipv4>()?.total_length().into(),
+            EtherTypes::Ipv6 => eth.parse_to::<Ipv6>()?.payload_length() as u32 + 40,
+            _ => bail!("Unsupported EtherType"),
+        };
         let packet = PacketFeature {
             dir: segment.dir.into(),
             offset: delta_ns,
-            sz: ipv4.total_length().into(),
+            sz,
         };
+
+        #[cfg(feature = "feature-trace")]
+        trace!(
+            "{} dir={} offset={} sz={}",
+            five_tuple,
+            dir,
+            packet.offset,
+            packet.sz
+        );
+
         Ok(packet)
     }
 }
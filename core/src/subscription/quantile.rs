@@ -0,0 +1,131 @@
+//! Streaming quantile estimation shared by every subscribable feature type.
+
+/// A single-pass estimator of the `p`-th quantile using the P² (piecewise-parabolic) algorithm
+/// of Jain & Chlamtac, which tracks only five markers instead of buffering every observation.
+#[derive(Debug, Clone)]
+pub(crate) struct P2Quantile {
+    p: f64,
+    /// Marker heights, q_1..q_5.
+    q: [f64; 5],
+    /// Marker positions, n_1..n_5.
+    n: [i64; 5],
+    /// Desired marker positions, n'_1..n'_5.
+    np: [f64; 5],
+    /// Desired-position increments, applied to `np` on every observation.
+    dn: [f64; 5],
+    /// Buffers the first 5 observations used to initialize the markers.
+    init: Vec<f64>,
+}
+
+impl P2Quantile {
+    pub(crate) fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            q: [0.0; 5],
+            n: [0; 5],
+            np: [0.0; 5],
+            dn: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            init: Vec::with_capacity(5),
+        }
+    }
+
+    pub(crate) fn update(&mut self, x: f64) {
+        if self.init.len() < 5 {
+            self.init.push(x);
+            if self.init.len() == 5 {
+                self.init.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                for i in 0..5 {
+                    self.q[i] = self.init[i];
+                    self.n[i] = i as i64 + 1;
+                }
+                self.np = [
+                    1.0,
+                    1.0 + 2.0 * self.p,
+                    1.0 + 4.0 * self.p,
+                    3.0 + 2.0 * self.p,
+                    5.0,
+                ];
+            }
+            return;
+        }
+
+        let k = if x < self.q[0] {
+            self.q[0] = x;
+            0
+        } else if x >= self.q[4] {
+            self.q[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| self.q[i] <= x && x < self.q[i + 1]).unwrap()
+        };
+        for n_i in self.n.iter_mut().skip(k + 1) {
+            *n_i += 1;
+        }
+        for i in 0..5 {
+            self.np[i] += self.dn[i];
+        }
+
+        for i in 1..4 {
+            let d = self.np[i] - self.n[i] as f64;
+            if (d >= 1.0 && self.n[i + 1] - self.n[i] > 1) || (d <= -1.0 && self.n[i - 1] - self.n[i] < -1) {
+                let d = d.signum();
+                let bracket = ((self.n[i] - self.n[i - 1]) as f64 + d) * (self.q[i + 1] - self.q[i])
+                    / (self.n[i + 1] - self.n[i]) as f64
+                    + ((self.n[i + 1] - self.n[i]) as f64 - d) * (self.q[i] - self.q[i - 1])
+                        / (self.n[i] - self.n[i - 1]) as f64;
+                let parabolic = self.q[i] + d / (self.n[i + 1] - self.n[i - 1]) as f64 * bracket;
+                self.q[i] = if self.q[i - 1] < parabolic && parabolic < self.q[i + 1] {
+                    parabolic
+                } else {
+                    let j = (i as i64 + d as i64) as usize;
+                    self.q[i] + d * (self.q[j] - self.q[i]) / (self.n[j] - self.n[i]) as f64
+                };
+                self.n[i] += d as i64;
+            }
+        }
+    }
+
+    /// Returns the current quantile estimate, or `NAN` if no observations have been seen yet.
+    pub(crate) fn value(&self) -> f64 {
+        if self.init.len() < 5 {
+            if self.init.is_empty() {
+                return f64::NAN;
+            }
+            let mut sorted = self.init.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let idx = (((sorted.len() - 1) as f64) * self.p).round() as usize;
+            return sorted[idx];
+        }
+        self.q[2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_converges_on_uniform_data() {
+        let mut p2 = P2Quantile::new(0.5);
+        for x in 1..=1001 {
+            p2.update(x as f64);
+        }
+        // True median of 1..=1001 is 501; P^2 is an approximation, so allow slack.
+        assert!((p2.value() - 501.0).abs() < 5.0, "got {}", p2.value());
+    }
+
+    #[test]
+    fn quantile_before_five_observations_is_exact() {
+        let mut p2 = P2Quantile::new(0.5);
+        p2.update(3.0);
+        p2.update(1.0);
+        p2.update(2.0);
+        assert_eq!(p2.value(), 2.0);
+    }
+
+    #[test]
+    fn empty_quantile_is_nan() {
+        let p2 = P2Quantile::new(0.9);
+        assert!(p2.value().is_nan());
+    }
+}
@@ -34,7 +34,7 @@ struct Args {
 //#[filter("ipv4 and tcp and tls and (tls.sni ~ 'zoom\\.us' or tls.sni ~ 'nflxvideo\\.net' or tls.sni ~ 'ttvnw\\.net' or tls.sni ~ 'teams\\.microsoft\\.com' or tls.sni ~ 'facebook\\.com' or tls.sni ~ 'fbcdn\\.net' or tls.sni ~ 'twitter\\.com' or tls.sni ~ 'twimg\\.com')")]
 //#[filter("ipv4 and tcp and tls and (tls.sni ~ 'meet\\.google\\.com')")]
 //#[filter("ipv4 and tcp and tls and (tls.sni ~ 'teams\\.microsoft\\.com')")]
-#[filter("ipv4 and tcp and tls")]
+#[filter("ip and tcp and tls")]
 fn main() -> Result<()> {
     env_logger::init();
     let args = Args::parse();
@@ -1,4 +1,5 @@
 use retina_core::config::load_config;
+use retina_core::subscription::anonymize::CryptoPan;
 use retina_core::subscription::ConnectionFrame;
 use retina_core::Runtime;
 use retina_filtergen::filter;
@@ -40,6 +41,7 @@ fn main() -> Result<()> {
 
     let key_bytes = read(&args.keyfile).expect("Failed to read key file.");
     let key: [u8; 16] = key_bytes.try_into().expect("Incorrect key size.");
+    let anonymizer = CryptoPan::new(key);
 
     let file = File::create(&args.outfile)?;
     let pcap_writer = Mutex::new(PcapWriter::new(file)?);
@@ -48,8 +50,8 @@ fn main() -> Result<()> {
         if let Some(mut eth) = Ethernet::owned(pkt.data) {
             let payload = Ethernet::payload_mut(&mut eth);
             if let Some(mut ipv4) = Ipv4::new(payload) {
-                let src_anon = ipcrypt::encrypt(Ipv4::get_source(&ipv4), &key);
-                let dst_anon = ipcrypt::encrypt(Ipv4::get_destination(&ipv4), &key);
+                let src_anon = anonymizer.anonymize_v4(Ipv4::get_source(&ipv4));
+                let dst_anon = anonymizer.anonymize_v4(Ipv4::get_destination(&ipv4));
                 Ipv4::set_source(&mut ipv4, src_anon);
                 Ipv4::set_destination(&mut ipv4, dst_anon);
             }
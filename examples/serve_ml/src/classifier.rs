@@ -0,0 +1,104 @@
+//! Pluggable inference backends.
+//!
+//! `BatchSink` only needs to turn a block of feature rows into a block of labels; it shouldn't
+//! care whether that happens via a smartcore decision tree, a smartcore random forest, or an
+//! exported ONNX graph. `Classifier` is that seam: swapping models becomes a matter of pointing
+//! `load_classifier` at a different file, not rewriting the packet path.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use smartcore::api::Predictor;
+use smartcore::ensemble::random_forest_classifier::RandomForestClassifier;
+use smartcore::linalg::basic::matrix::DenseMatrix;
+use smartcore::tree::decision_tree_classifier::DecisionTreeClassifier;
+
+/// A trained classifier that predicts integer labels for a batch of feature rows.
+pub trait Classifier: Send {
+    /// `rows` is a row-major arena holding `rows.len() / n_features` feature rows.
+    fn predict_batch(&self, rows: &[f32], n_features: usize) -> Vec<usize>;
+}
+
+/// Converts a row-major `f32` arena into the `DenseMatrix<f64>` smartcore's `Predictor`s expect.
+fn to_dense_matrix(rows: &[f32], n_features: usize) -> DenseMatrix<f64> {
+    let n_rows = rows.len() / n_features;
+    let rows: Vec<f64> = rows.iter().map(|&x| x as f64).collect();
+    DenseMatrix::new(n_rows, n_features, rows, false)
+}
+
+impl Classifier for DecisionTreeClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>> {
+    fn predict_batch(&self, rows: &[f32], n_features: usize) -> Vec<usize> {
+        self.predict(&to_dense_matrix(rows, n_features)).unwrap()
+    }
+}
+
+impl Classifier for RandomForestClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>> {
+    fn predict_batch(&self, rows: &[f32], n_features: usize) -> Vec<usize> {
+        self.predict(&to_dense_matrix(rows, n_features)).unwrap()
+    }
+}
+
+/// Runs inference against an exported ONNX graph. Requires the `onnx` feature; without it,
+/// `load_classifier` rejects `.onnx` model files at load time rather than failing to build.
+#[cfg(feature = "onnx")]
+struct OnnxClassifier {
+    model: tract_onnx::prelude::TypedRunnableModel<tract_onnx::prelude::TypedModel>,
+}
+
+#[cfg(feature = "onnx")]
+impl OnnxClassifier {
+    fn load(path: &Path) -> Result<Self> {
+        use tract_onnx::prelude::*;
+        let model = tract_onnx::onnx()
+            .model_for_path(path)?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(OnnxClassifier { model })
+    }
+}
+
+#[cfg(feature = "onnx")]
+impl Classifier for OnnxClassifier {
+    fn predict_batch(&self, rows: &[f32], n_features: usize) -> Vec<usize> {
+        use tract_onnx::prelude::*;
+        let n_rows = rows.len() / n_features;
+        let input = tract_ndarray::Array2::from_shape_vec((n_rows, n_features), rows.to_vec())
+            .expect("row-major arena length must be a multiple of n_features");
+        let result = self
+            .model
+            .run(tvec!(input.into_tensor().into()))
+            .expect("ONNX inference failed");
+        result[0]
+            .to_array_view::<i64>()
+            .expect("ONNX model must emit an integer label tensor")
+            .iter()
+            .map(|&label| label as usize)
+            .collect()
+    }
+}
+
+/// Loads a classifier from `path`, dispatching on its extension: `.onnx` for the ONNX backend,
+/// `.rf.bin` for a bincode-serialized smartcore random forest, and anything else for a
+/// bincode-serialized smartcore decision tree (the pre-existing default).
+pub fn load_classifier(path: &Path) -> Result<Box<dyn Classifier>> {
+    let name = path.to_string_lossy();
+    if name.ends_with(".onnx") {
+        #[cfg(feature = "onnx")]
+        {
+            return Ok(Box::new(OnnxClassifier::load(path)?));
+        }
+        #[cfg(not(feature = "onnx"))]
+        bail!("loading {:?} requires building with --features onnx", path);
+    }
+    if name.ends_with(".rf.bin") {
+        let mut file = File::open(path)?;
+        let clf: RandomForestClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>> =
+            bincode::deserialize_from(&mut file)?;
+        return Ok(Box::new(clf));
+    }
+    let mut file = File::open(path)?;
+    let clf: DecisionTreeClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>> =
+        bincode::deserialize_from(&mut file)?;
+    Ok(Box::new(clf))
+}
@@ -1,30 +1,87 @@
-/// Build: cargo b --features dur,s_bytes_sum,s_bytes_mean --bin serve_ml
-/// Run: sudo env LD_LIBRARY_PATH=$LD_LIBRARY_PATH RUST_LOG=info ./target/debug/serve_ml -c configs/offline.toml -m /mnt/netml/datasets/app_class/test/rust_rf.bin -o pred.json
+/// Build: cargo b --bin serve_ml
+/// Run: sudo env LD_LIBRARY_PATH=$LD_LIBRARY_PATH RUST_LOG=info ./target/debug/serve_ml -c configs/offline.toml -m /mnt/netml/datasets/app_class/test/rust_rf.bin -o pred.json --columns dur,s_bytes_sum,d_bytes_sum,tcp_rtt
 /// /mnt/netml/datasets/iot_lite/pkts_5/features_40961
 
 
+mod classifier;
+
+use classifier::{load_classifier, Classifier};
+
 use retina_core::config::load_config;
 use retina_core::config::RuntimeConfig;
-use retina_core::subscription::features::Features;
+use retina_core::subscription::feature_extractor::{init_feature_extractors, DynamicFeatures};
 use retina_core::Runtime;
 use retina_filtergen::filter;
 
-use std::fs::File;
-use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::fs::File;
+use std::io::Write;
 
 use anyhow::Result;
 use clap::Parser;
 use serde::Serialize;
 
-// use smartcore::dataset::Dataset;
-// use smartcore::linalg::basic::arrays::{Array, Array2};
-use smartcore::linalg::basic::matrix::DenseMatrix;
-// use smartcore::metrics::accuracy;
-// use smartcore::model_selection::train_test_split;
-use smartcore::tree::decision_tree_classifier::DecisionTreeClassifier;
-// use smartcore::ensemble::random_forest_classifier::RandomForestClassifier;
+/// Number of rows to accumulate before running a batch prediction.
+const BATCH_SIZE: usize = 64;
+
+/// Time since the last flush after which a `push` flushes even a partial batch. The deadline is
+/// checked only when a row is pushed; a partial batch that receives no further rows stays
+/// buffered until the final `flush` at shutdown.
+const BATCH_DEADLINE: Duration = Duration::from_millis(100);
+
+/// Accumulates feature rows into a reusable arena and classifies them in blocks, amortizing
+/// matrix construction and prediction dispatch over many connections instead of paying that
+/// cost once per terminated connection. Holds the model as a `Box<dyn Classifier>` so the batching
+/// logic doesn't depend on which inference backend produced it.
+struct BatchSink<'a> {
+    clf: Box<dyn Classifier>,
+    /// Row-major arena of pending feature rows.
+    arena: Vec<f32>,
+    row_cnt: usize,
+    last_flush: Instant,
+    on_result: Box<dyn Fn(usize) + Send + 'a>,
+}
+
+impl<'a> BatchSink<'a> {
+    fn new(clf: Box<dyn Classifier>, on_result: impl Fn(usize) + Send + 'a) -> Self {
+        BatchSink {
+            clf,
+            arena: Vec::new(),
+            row_cnt: 0,
+            last_flush: Instant::now(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    /// Appends one feature row to the arena, flushing immediately if the batch is full or the
+    /// deadline for the current partial batch has elapsed.
+    fn push(&mut self, row: Vec<f32>) {
+        self.arena.extend(row);
+        self.row_cnt += 1;
+        if self.row_cnt >= BATCH_SIZE || self.last_flush.elapsed() >= BATCH_DEADLINE {
+            self.flush();
+        }
+    }
+
+    /// Runs a single batched `predict_batch` over every buffered row, dispatching each row's
+    /// label to `on_result`, then clears the arena.
+    fn flush(&mut self) {
+        self.last_flush = Instant::now();
+        if self.row_cnt == 0 {
+            return;
+        }
+        let n_features = self.arena.len() / self.row_cnt;
+        let rows = std::mem::take(&mut self.arena);
+        let preds = self.clf.predict_batch(&rows, n_features);
+        for pred in preds {
+            (self.on_result)(pred);
+        }
+        self.row_cnt = 0;
+    }
+}
 
 // Define command-line arguments.
 #[derive(Parser, Debug)]
@@ -35,6 +92,10 @@ struct Args {
     model_file: PathBuf,
     #[clap(short, long, parse(from_os_str), value_name = "OUT_FILE")]
     outfile: PathBuf,
+    /// Comma-separated extractor names, in the column order the model was trained on. See
+    /// `feature_extractor::EXTRACTOR_REGISTRY` for the full set of names.
+    #[clap(long, value_delimiter = ',', default_value = "dur,s_bytes_sum,d_bytes_sum,tcp_rtt")]
+    columns: Vec<String>,
 }
 
 #[filter("ipv4 and tcp and tls")]
@@ -45,28 +106,34 @@ fn main() -> Result<()> {
 
     let mut file = File::create(args.outfile)?;
     let cnt = AtomicUsize::new(0);
-    let clf = load_clf(&args.model_file)?;
-
-    let callback = |features: Features| {
-        // //#[cfg(feature = "capture_start")]
-        // println!("Syn ts: {}", features.syn_ts);
-        let feature_vec = features.feature_vec;
-        let instance = DenseMatrix::new(1, feature_vec.len(), feature_vec, false);
-        //   let start = Instant::now();
-        let pred = clf.predict(&instance).unwrap();
-        //   println!("predict: {:?}", start.elapsed());
-        //println!("{:?}", pred);
-        
-        cnt.fetch_add(1, Ordering::Relaxed);
-        // let res = serde_json::to_string(&(conn.sni, pred[0])).unwrap();
-        // let res = serde_json::to_string(&pred[0]).unwrap();
+    let clf = load_classifier(&args.model_file)?;
+
+    // `init_feature_extractors` stores its argument for the life of the process, so each name is
+    // leaked to `&'static str` once at startup rather than cloned per connection.
+    let columns: Vec<&'static str> = args
+        .columns
+        .into_iter()
+        .map(|name| &*Box::leak(name.into_boxed_str()))
+        .collect();
+    init_feature_extractors(columns);
+
+    let batch_sink = Mutex::new(BatchSink::new(clf, |_pred| {
+        // let res = serde_json::to_string(&pred).unwrap();
         // let mut wtr = file.lock().unwrap();
         // wtr.write_all(res.as_bytes()).unwrap();
         // wtr.write_all(b"\n").unwrap();
+        cnt.fetch_add(1, Ordering::Relaxed);
+    }));
+
+    let callback = |features: DynamicFeatures| {
+        batch_sink.lock().unwrap().push(features.values);
     };
     let mut runtime = Runtime::new(config.clone(), filter, callback)?;
     runtime.run();
 
+    // Flush any rows still buffered from the last partial batch.
+    batch_sink.lock().unwrap().flush();
+
     let output = Output {
         config,
         num_conns: cnt.load(Ordering::SeqCst),
@@ -79,16 +146,6 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-/// Loads a trained classifier from `file`.
-fn load_clf(
-    fname: &PathBuf,
-) -> Result<DecisionTreeClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>>> {
-    let mut file = File::open(fname)?;
-    let clf: DecisionTreeClassifier<f64, usize, DenseMatrix<f64>, Vec<usize>> =
-        bincode::deserialize_from(&mut file)?;
-    Ok(clf)
-}
-
 #[derive(Debug, Serialize)]
 struct Output {
     config: RuntimeConfig,